@@ -38,16 +38,14 @@ fn EVAL(input: MalVal, env: &ReplEnv) -> MalResult {
                 return Ok(input);
             }
 
-            match eval_ast(list[0].clone(), env) {
-                Ok(MalVal::BuiltinFn(f)) => f(list[1..]
+            // SAFETY: 空リストは上で返している
+            match eval_ast(list.first().unwrap().clone(), env) {
+                Ok(MalVal::BuiltinFn(f)) => f(list
                     .iter()
+                    .skip(1)
                     .map(|item| EVAL(item.clone(), env))
                     .collect::<Result<_, _>>()?),
-                Ok(_) => Err(MalError::InvalidType(
-                    "eliminated".to_string(),
-                    "symbol".to_string(),
-                    list[1].type_str(),
-                )),
+                Ok(not_func) => Err(MalError::InvalidType(not_func, "function".to_string())),
                 Err(e) => Err(e),
             }
         }
@@ -1,5 +1,3 @@
-#![feature(iterator_try_reduce)]
-
 use itertools::Itertools;
 use rustyline::error::ReadlineError;
 use rustyline::DefaultEditor;
@@ -38,6 +36,7 @@ fn EVAL(input: MalVal, env: &mut Env) -> MalResult {
             if list.is_empty() {
                 return Ok(input);
             }
+            let list = &list.iter().cloned().collect_vec();
 
             // 特殊フォームの処理
             if let MalVal::Symbol(s) = &list[0] {
@@ -57,9 +56,8 @@ fn EVAL(input: MalVal, env: &mut Env) -> MalResult {
                             return Ok(val);
                         } else {
                             return Err(MalError::InvalidType(
-                                s.to_string(),
+                                list[1].clone(),
                                 "symbol".to_string(),
-                                list[1].type_str(),
                             ));
                         }
                     }
@@ -73,9 +71,8 @@ fn EVAL(input: MalVal, env: &mut Env) -> MalResult {
                         }
 
                         let mut new_env = Env::new(Some(env));
-                        if let MalVal::List(bindings, _) | MalVal::Vector(bindings, _) = &list[1] {
+                        if let Some(bindings) = list[1].seq_iter() {
                             bindings
-                                .iter()
                                 .chain(std::iter::once(&MalVal::Nil)) // 奇数個の場合に対応するため
                                 .tuples()
                                 .try_for_each(|(k, v)| {
@@ -84,19 +81,11 @@ fn EVAL(input: MalVal, env: &mut Env) -> MalResult {
                                         new_env.set(s.to_string(), val);
                                         Ok(())
                                     } else {
-                                        Err(MalError::InvalidType(
-                                            printer::pr_str(k, true),
-                                            "symbol".to_string(),
-                                            k.type_str(),
-                                        ))
+                                        Err(MalError::InvalidType(k.clone(), "symbol".to_string()))
                                     }
                                 })?;
                         } else {
-                            return Err(MalError::InvalidType(
-                                printer::pr_str(&list[1], true),
-                                "list".to_string(),
-                                list[1].type_str(),
-                            ));
+                            return Err(MalError::InvalidType(list[1].clone(), "list".to_string()));
                         }
 
                         return EVAL(list[2].clone(), &mut new_env);
@@ -106,15 +95,12 @@ fn EVAL(input: MalVal, env: &mut Env) -> MalResult {
             }
 
             match eval_ast(list[0].clone(), env) {
-                Ok(MalVal::BuiltinFn(f)) => f(list[1..]
+                Ok(MalVal::BuiltinFn(f)) => f(list
                     .iter()
+                    .skip(1)
                     .map(|item| EVAL(item.clone(), env))
                     .collect::<Result<_, _>>()?),
-                Ok(t) => Err(MalError::InvalidType(
-                    printer::pr_str(&t, true),
-                    "function".to_string(),
-                    t.type_str(),
-                )),
+                Ok(t) => Err(MalError::InvalidType(t, "function".to_string())),
                 Err(e) => Err(e),
             }
         }
@@ -1,5 +1,4 @@
-#![feature(iterator_try_reduce)]
-#![feature(iterator_try_collect)]
+#![cfg_attr(test, feature(iterator_try_reduce))]
 
 use std::hint::unreachable_unchecked;
 
@@ -44,6 +43,7 @@ fn EVAL(input: &MalVal, env: &mut Env) -> MalResult {
         if list.is_empty() {
             return Ok(input.clone());
         }
+        let list = &list.iter().cloned().collect_vec();
 
         // 特殊フォームの処理
         if let MalVal::Symbol(s) = &list[0] {
@@ -61,6 +61,7 @@ fn EVAL(input: &MalVal, env: &mut Env) -> MalResult {
             // SAFETY: Listの場合はeval_astで必ずMalVal::Listが返る
             unsafe { unreachable_unchecked() }
         };
+        let list = list.iter().cloned().collect_vec();
         // TODO: vecやhashmapも関数のように扱えるようにする
         match &list[0] {
             MalVal::BuiltinFn(f) => f(list[1..].to_vec()),
@@ -91,9 +92,8 @@ fn EVAL(input: &MalVal, env: &mut Env) -> MalResult {
                 EVAL(&f.body, &mut new_env)
             }
             not_func => Err(MalError::InvalidType(
-                printer::pr_str(not_func, true),
+                not_func.clone(),
                 "function".to_string(),
-                not_func.type_str(),
             )),
         }
     } else {
@@ -144,11 +144,7 @@ fn special_def(list: &[MalVal], env: &mut Env) -> MalResult {
         env.set(s.to_string(), val.clone());
         Ok(val)
     } else {
-        Err(MalError::InvalidType(
-            printer::pr_str(&list[1], true),
-            "symbol".to_string(),
-            list[1].type_str(),
-        ))
+        Err(MalError::InvalidType(list[1].clone(), "symbol".to_string()))
     }
 }
 
@@ -162,9 +158,8 @@ fn special_let(list: &[MalVal], env: &Env) -> MalResult {
     }
 
     let mut new_env = Env::new(Some(env));
-    if let MalVal::List(bindings, _) | MalVal::Vector(bindings, _) = &list[1] {
+    if let Some(bindings) = list[1].seq_iter() {
         bindings
-            .iter()
             .chain(std::iter::once(&MalVal::Nil)) // 奇数個の場合に対応するため
             .tuples()
             .try_for_each(|(k, v)| {
@@ -173,18 +168,13 @@ fn special_let(list: &[MalVal], env: &Env) -> MalResult {
                     new_env.set(s.to_string(), val);
                     Ok(())
                 } else {
-                    Err(MalError::InvalidType(
-                        printer::pr_str(k, true),
-                        "symbol".to_string(),
-                        k.type_str(),
-                    ))
+                    Err(MalError::InvalidType(k.clone(), "symbol".to_string()))
                 }
             })?;
     } else {
         return Err(MalError::InvalidType(
-            printer::pr_str(&list[1], true),
+            list[1].clone(),
             "list or vec".to_string(),
-            list[1].type_str(),
         ));
     }
 
@@ -227,9 +217,11 @@ fn special_fn(list: &[MalVal], env: &Env) -> MalResult {
         ));
     }
 
-    if let MalVal::List(params, _) | MalVal::Vector(params, _) = &list[1] {
+    if let Some(params) = list[1].seq_iter() {
+        let params = params.cloned().collect_vec();
         let ampersand_error = "invalid function definition: & in incorrect position";
         Ok(MalVal::func(Closure {
+            name: None,
             // 逆順で引数をチェックする
             // [a b & c] => (& c), (a b)
             rev_params: params.rchunks(2).enumerate().try_fold(
@@ -268,11 +260,7 @@ fn special_fn(list: &[MalVal], env: &Env) -> MalResult {
                         }
                     }
                     (_, x, Some(MalVal::Symbol(_))) | (_, _, Some(x)) | (_, x, None) => {
-                        Err(MalError::InvalidType(
-                            printer::pr_str(x, true),
-                            "symbol".to_string(),
-                            x.type_str(),
-                        ))
+                        Err(MalError::InvalidType(x.clone(), "symbol".to_string()))
                     }
                 },
             )?, // NOTE: vecは逆順になっている
@@ -281,9 +269,8 @@ fn special_fn(list: &[MalVal], env: &Env) -> MalResult {
         }))
     } else {
         Err(MalError::InvalidType(
-            printer::pr_str(&list[1], true),
+            list[1].clone(),
             "list or vec".to_string(),
-            list[1].type_str(),
         ))
     }
 }
@@ -332,7 +319,7 @@ mod tests {
 
         let mut core_env: Env = [(
             "+".to_string(),
-            int_op!("+", |a, b| Ok(MalVal::Number(a + b))),
+            int_op!("+", i64::checked_add, |a, b| Ok(a + b), |a, b| a + b),
         )]
         .into();
         // println!("{:?}", super::EVAL(&nested_fn, &mut env).unwrap());
@@ -360,7 +347,7 @@ mod tests {
         // Env {outer: Env {a: 5}, b: 7}
         let core_env: Env = [(
             "+".to_string(),
-            int_op!("+", |a, b| Ok(MalVal::Number(a + b))),
+            int_op!("+", i64::checked_add, |a, b| Ok(a + b), |a, b| a + b),
         )]
         .into();
         let mut outer_env = Env::new(Some(&core_env));
@@ -389,7 +376,7 @@ mod tests {
         // Env {a: 7}
         let core_env: Env = [(
             "+".to_string(),
-            int_op!("+", |a, b| Ok(MalVal::Number(a + b))),
+            int_op!("+", i64::checked_add, |a, b| Ok(a + b), |a, b| a + b),
         )]
         .into();
         let mut env = Env::new(Some(&core_env));
@@ -413,13 +400,12 @@ mod tests {
                 &MalVal::list(vec![func]),
                 &mut [(
                     "count".to_string(),
-                    MalVal::BuiltinFn(|args| match args.get(0) {
+                    MalVal::BuiltinFn(|args| match args.first() {
                         None | Some(MalVal::Nil) => Ok(MalVal::Number(0)),
                         Some(MalVal::List(list, _)) => Ok(MalVal::Number(list.len() as i64)),
                         Some(z) => Err(rustymal::types::MalError::InvalidType(
-                            rustymal::printer::pr_str(z, true),
+                            z.clone(),
                             "nil or list".to_string(),
-                            z.type_str(),
                         )),
                     }),
                 )]
@@ -1,13 +1,30 @@
+use std::cell::RefCell;
 use std::hint::unreachable_unchecked;
+use std::rc::Rc;
 
 use itertools::Itertools;
 use rustyline::error::ReadlineError;
 use rustyline::DefaultEditor;
 use rustymal::core;
 use rustymal::env::Env;
+use rustymal::lazy::LazySeq;
+use rustymal::list::List;
 use rustymal::printer;
 use rustymal::reader;
-use rustymal::types::{Arity, Closure, MalError, MalResult, MalVal};
+use rustymal::types::{Arity, Closure, Frame, MalError, MalResult, MalVal};
+
+thread_local! {
+    // 評価中のユーザー定義関数の呼び出しスタック
+    static CALL_STACK: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+    // handler-bindで設定されたハンドラと、評価中のtry*の境界
+    static HANDLERS: RefCell<Vec<Handler>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(Clone)]
+enum Handler {
+    Bind(MalVal, MalVal), // (コンディションの種類, ハンドラ関数)
+    Try,                  // catch*を持つtry*. エラーはここより外側のハンドラに通知しない
+}
 
 fn main() {
     let mut env = core::env();
@@ -20,7 +37,7 @@ fn main() {
         let mut editor = DefaultEditor::new().unwrap();
         let line = editor.readline("user=> ");
         match line {
-            Ok(line) => println!("{}", rep(line, &mut env).unwrap_or_else(|e| e.to_string())),
+            Ok(line) => println!("{}", rep(line, &mut env).unwrap_or_else(|e| e.report())),
             Err(ReadlineError::Interrupted) => continue,
             Err(ReadlineError::Eof) => break,
             Err(err) => {
@@ -36,7 +53,25 @@ fn READ(input: String) -> MalResult {
 }
 
 #[allow(non_snake_case)]
-fn EVAL(mut input: MalVal, env: &mut Env) -> MalResult {
+fn EVAL(input: MalVal, env: &mut Env) -> MalResult {
+    let depth = CALL_STACK.with_borrow(Vec::len);
+    let result = eval_loop(input, env, depth).or_else(|e| match e {
+        MalError::Traced(_, _) | MalError::Restart(_, _) => Err(e),
+        // 巻き戻す前に、エラーが発生した地点でハンドラに通知する
+        _ => {
+            let e = CALL_STACK.with_borrow(|stack| e.with_trace(stack));
+            signal(&e, MalVal::from_error(e.clone()), true)?;
+            Err(e)
+        }
+    });
+    CALL_STACK.with_borrow_mut(|stack| stack.truncate(depth));
+    result
+}
+
+// depthはこの評価を開始した時点の呼び出しスタックの深さ
+fn eval_loop(mut input: MalVal, env: &mut Env, depth: usize) -> MalResult {
+    // TCOで環境を差し替えても呼び出し元の環境に影響しないように複製しておく
+    let env = &mut env.clone();
     loop {
         if let MalVal::List(ref list, _) = input {
             if list.is_empty() {
@@ -44,66 +79,58 @@ fn EVAL(mut input: MalVal, env: &mut Env) -> MalResult {
             }
 
             // 特殊フォームの処理
-            if let MalVal::Symbol(s) = &list[0] {
+            if let Some(MalVal::Symbol(s)) = list.first() {
+                // 特殊フォームには先頭のシンボルを除いた引数のリストを渡す
+                let args = &list.rest();
                 match s.as_str() {
-                    "def!" => return special_def(list, env),
-                    "fn*" => return special_fn(list, env),
+                    "def!" => return special_def(args, env),
+                    "fn*" => return special_fn(args, env),
+                    "try*" => return special_try(args, env),
+                    "lazy-seq" => return special_lazy_seq(args, env),
+                    "signal" => return special_signal(args, env),
+                    "handler-bind" => return special_handler_bind(args, env),
+                    "restart-case" => return special_restart_case(args, env),
                     "do" => {
-                        input = special_do(list.to_vec(), env)?;
+                        input = special_do(args, env)?;
                         continue;
                     }
                     "if" => {
-                        input = special_if(list, env)?;
+                        input = special_if(args, env)?;
                         continue;
                     }
                     "let*" => {
-                        input = special_let(list, env)?;
+                        input = special_let(args, env)?;
                         continue;
                     }
                     _ => {}
                 };
             }
 
-            let MalVal::List(list, _) = eval_ast(input, env)? else {
-                // SAFETY: Listの場合はeval_astで必ずMalVal::Listが返る
+            let form = input.clone();
+            let MalVal::List(list, _) = &form else {
+                // SAFETY: inputはListであることを確認済み
                 unsafe { unreachable_unchecked() }
             };
-            // TODO: vecやhashmapも関数のように扱えるようにする
-            match &list[0] {
-                MalVal::BuiltinFn(f) => return f(list[1..].to_vec()),
+            let mut items = list.iter();
+            // SAFETY: 空リストは上で返している
+            let func = EVAL(items.next().unwrap().clone(), env)?;
+            let args: Vec<MalVal> = items.map(|x| EVAL(x.clone(), env)).try_collect()?;
+            match &func {
+                MalVal::BuiltinFn(f) => return f(args),
                 MalVal::Func(f, _) => {
-                    let (rev_p, v) = f.rev_params.clone();
-                    if v.is_some() {
-                        if rev_p.len() > list.len() - 1 {
-                            return Err(MalError::WrongArity(
-                                "function".to_string(),
-                                Arity::Variadic(rev_p.len()),
-                                list.len() - 1,
-                            ));
-                        }
-                    } else if rev_p.len() != list.len() - 1 {
-                        return Err(MalError::WrongArity(
-                            "function".to_string(),
-                            Arity::Fixed(rev_p.len()),
-                            list.len() - 1,
-                        ));
-                    }
-
-                    *env = Env::with_bind(
-                        Some(&f.env),
-                        rev_p.into_iter().rev(),
-                        v,
-                        list[1..].iter().cloned(),
-                    );
+                    *env = bind_args(f, &func, &args)?;
+                    // 末尾呼び出しでは直前のフレームを置き換える
+                    CALL_STACK.with_borrow_mut(|stack| {
+                        stack.truncate(depth);
+                        stack.push(Frame {
+                            name: f.name.clone(),
+                            form: form.clone(),
+                        });
+                    });
                     input = f.body.clone();
                 }
-                not_func => {
-                    return Err(MalError::InvalidType(
-                        printer::pr_str(not_func, true),
-                        "function".to_string(),
-                        not_func.type_str(),
-                    ))
-                }
+                // コレクションやキーワードの呼び出し
+                _ => return func.apply(args),
             }
         } else {
             return eval_ast(input, env);
@@ -111,14 +138,76 @@ fn EVAL(mut input: MalVal, env: &mut Env) -> MalResult {
     }
 }
 
+// 関数の仮引数に実引数を束縛した環境を作る
+fn bind_args(f: &Closure, func: &MalVal, args: &[MalVal]) -> Result<Env, MalError> {
+    let (rev_p, v) = f.rev_params.clone();
+    if v.is_some() {
+        if rev_p.len() > args.len() {
+            return Err(MalError::WrongArity(
+                f.name_str().to_string(),
+                Arity::Variadic(rev_p.len()),
+                args.len(),
+            ));
+        }
+    } else if rev_p.len() != args.len() {
+        return Err(MalError::WrongArity(
+            f.name_str().to_string(),
+            Arity::Fixed(rev_p.len()),
+            args.len(),
+        ));
+    }
+
+    // 名前付き関数は自身の名前で再帰呼び出しできるようにする
+    // 仮引数が同じ名前を隠せるように、名前は仮引数の外側の環境に束縛する
+    // NOTE: クロージャの環境に直接束縛すると循環参照になるため呼び出し時に束縛する
+    let outer = match &f.name {
+        Some(name) => {
+            let mut outer = Env::new(Some(&f.env));
+            outer.set(name.as_ref(), func.clone());
+            outer
+        }
+        None => f.env.clone(),
+    };
+    Ok(Env::with_bind(
+        Some(&outer),
+        rev_p.into_iter().rev(),
+        v,
+        args.iter().cloned(),
+    ))
+}
+
+// 評価済みの関数を引数に適用する
+fn apply_fn(func: &MalVal, args: Vec<MalVal>) -> MalResult {
+    match func {
+        MalVal::BuiltinFn(f) => f(args),
+        // 組み込み関数から呼ばれた場合も呼び出しスタックに積む
+        MalVal::Func(f, _) => {
+            let env = &mut bind_args(f, func, &args)?;
+            let form = MalVal::list(std::iter::once(func.clone()).chain(args).collect());
+            CALL_STACK.with_borrow_mut(|stack| {
+                stack.push(Frame {
+                    name: f.name.clone(),
+                    form,
+                })
+            });
+            let result = EVAL(f.body.clone(), env);
+            CALL_STACK.with_borrow_mut(|stack| stack.pop());
+            result
+        }
+        _ => func.apply(args),
+    }
+}
+
 #[allow(non_snake_case)]
-fn PRINT(input: &MalVal) -> String {
-    printer::pr_str(input, true)
+fn PRINT(input: &MalVal) -> Result<String, MalError> {
+    printer::repl_pr_str(input)
 }
 
 // READ -> EVAL -> PRINT
 fn rep(input: String, env: &mut Env) -> Result<String, MalError> {
-    Ok(PRINT(&EVAL(READ(input)?, env)?))
+    // 組み込み関数からユーザー定義関数を呼べるようにする
+    MalVal::set_applier(apply_fn);
+    PRINT(&EVAL(READ(input)?, env)?)
 }
 
 fn eval_ast(ast: MalVal, env: &mut Env) -> MalResult {
@@ -135,75 +224,75 @@ fn eval_ast(ast: MalVal, env: &mut Env) -> MalResult {
                 .map(|(k, v)| Ok((k.clone(), EVAL(v.clone(), env)?)))
                 .try_collect()?,
         )),
+        MalVal::HashSet(set, _) => Ok(MalVal::hashset(
+            set.iter()
+                .map(|item| EVAL(item.clone(), env))
+                .try_collect()?,
+        )),
         _ => Ok(ast.clone()),
     }
 }
 
-fn special_def(list: &[MalVal], env: &mut Env) -> MalResult {
-    if list.len() != 3 {
+fn special_def(args: &List, env: &mut Env) -> MalResult {
+    let Some((name, val)) = args.iter().collect_tuple() else {
         return Err(MalError::WrongArity(
             "def!".to_string(),
             Arity::Fixed(2),
-            list.len() - 1,
+            args.len(),
         ));
-    }
+    };
 
-    if let MalVal::Symbol(s) = &list[1] {
-        let val = EVAL(list[2].clone(), env)?;
+    if let MalVal::Symbol(s) = name {
+        let val = EVAL(val.clone(), env)?;
         env.set(s.to_string(), val.clone());
         Ok(val)
     } else {
-        Err(MalError::InvalidType(
-            printer::pr_str(&list[1], true),
-            "symbol".to_string(),
-            list[1].type_str(),
-        ))
+        Err(MalError::InvalidType(name.clone(), "symbol".to_string()))
     }
 }
 
-fn special_do(mut list: Vec<MalVal>, env: &mut Env) -> MalResult {
-    let last = list.pop().unwrap_or(MalVal::Nil);
-    list.into_iter().try_for_each(|x| {
-        EVAL(x, env)?;
-        Ok(())
-    })?;
-    Ok(last)
+// 最後の式以外を評価し、最後の式は評価せずに返す
+fn special_do(args: &List, env: &mut Env) -> MalResult {
+    let mut xs = args.iter();
+    let Some(mut last) = xs.next() else {
+        return Ok(MalVal::Nil);
+    };
+    for x in xs {
+        EVAL(last.clone(), env)?;
+        last = x;
+    }
+    Ok(last.clone())
 }
 
-fn special_if(list: &[MalVal], env: &mut Env) -> MalResult {
-    if list.len() < 3 || list.len() > 4 {
+fn special_if(args: &List, env: &mut Env) -> MalResult {
+    let mut xs = args.iter();
+    let (Some(cond), Some(then), otherwise, None) = (xs.next(), xs.next(), xs.next(), xs.next())
+    else {
         return Err(MalError::WrongArity(
             "if".to_string(),
             Arity::JustOrOneLess(4),
-            list.len() - 1,
+            args.len(),
         ));
-    }
+    };
 
-    match EVAL(list[1].clone(), env)? {
-        MalVal::Bool(false) | MalVal::Nil => {
-            if list.len() == 4 {
-                Ok(list[3].clone())
-            } else {
-                Ok(MalVal::Nil)
-            }
-        }
-        _ => Ok(list[2].clone()),
+    match EVAL(cond.clone(), env)? {
+        MalVal::Bool(false) | MalVal::Nil => Ok(otherwise.cloned().unwrap_or(MalVal::Nil)),
+        _ => Ok(then.clone()),
     }
 }
 
-fn special_let(list: &[MalVal], env: &mut Env) -> MalResult {
-    if list.len() != 3 {
+fn special_let(args: &List, env: &mut Env) -> MalResult {
+    let Some((bindings, body)) = args.iter().collect_tuple() else {
         return Err(MalError::WrongArity(
             "let*".to_string(),
             Arity::Fixed(2),
-            list.len() - 1,
+            args.len(),
         ));
-    }
+    };
 
-    if let MalVal::List(bindings, _) | MalVal::Vector(bindings, _) = &list[1] {
+    if let Some(bindings) = bindings.seq_iter() {
         let mut new_env = Env::new(Some(env));
         bindings
-            .iter()
             .chain(std::iter::once(&MalVal::Nil)) // 奇数個の場合に対応するため
             .tuples()
             .try_for_each(|(k, v)| {
@@ -212,37 +301,48 @@ fn special_let(list: &[MalVal], env: &mut Env) -> MalResult {
                     new_env.set(s.to_string(), val);
                     Ok(())
                 } else {
-                    Err(MalError::InvalidType(
-                        printer::pr_str(k, true),
-                        "symbol".to_string(),
-                        k.type_str(),
-                    ))
+                    Err(MalError::InvalidType(k.clone(), "symbol".to_string()))
                 }
             })?;
 
         *env = new_env;
-        Ok(list[2].clone())
+        Ok(body.clone())
     } else {
         Err(MalError::InvalidType(
-            printer::pr_str(&list[1], true),
+            bindings.clone(),
             "list or vec".to_string(),
-            list[1].type_str(),
         ))
     }
 }
 
-fn special_fn(list: &[MalVal], env: &Env) -> MalResult {
-    if list.len() != 3 {
-        return Err(MalError::WrongArity(
-            "fn*".to_string(),
-            Arity::Fixed(2),
-            list.len() - 1,
-        ));
-    }
+fn special_fn(args: &List, env: &Env) -> MalResult {
+    // (fn* name [args] body) の場合は名前を取り出す
+    let mut xs = args.iter();
+    let (name, params, body) = match (xs.next(), xs.next(), xs.next(), xs.next()) {
+        (Some(MalVal::Symbol(name)), Some(params), Some(body), None) => {
+            (Some(Rc::from(name.as_str())), params, body)
+        }
+        (Some(params), Some(body), None, None) => (None, params, body),
+        (Some(name), Some(_), Some(_), None) => {
+            return Err(MalError::InvalidType(name.clone(), "symbol".to_string()))
+        }
+        _ => {
+            return Err(MalError::WrongArity(
+                "fn*".to_string(),
+                Arity::JustOrOneLess(3),
+                args.len(),
+            ))
+        }
+    };
+    closure(name, params, body, env)
+}
 
-    if let MalVal::List(params, _) | MalVal::Vector(params, _) = &list[1] {
+fn closure(name: Option<Rc<str>>, params: &MalVal, body: &MalVal, env: &Env) -> MalResult {
+    if let Some(params) = params.seq_iter() {
+        let params = params.cloned().collect_vec();
         let ampersand_error = "invalid function definition: & in incorrect position";
         Ok(MalVal::func(Closure {
+            name,
             // 逆順で引数をチェックする
             // [a b & c] => (& c), (a b)
             rev_params: params.rchunks(2).enumerate().try_fold(
@@ -281,22 +381,1270 @@ fn special_fn(list: &[MalVal], env: &Env) -> MalResult {
                         }
                     }
                     (_, x, Some(MalVal::Symbol(_))) | (_, _, Some(x)) | (_, x, None) => {
-                        Err(MalError::InvalidType(
-                            printer::pr_str(x, true),
-                            "symbol".to_string(),
-                            x.type_str(),
-                        ))
+                        Err(MalError::InvalidType(x.clone(), "symbol".to_string()))
                     }
                 },
             )?, // NOTE: vecは逆順になっている
-            body: list[2].clone(),
+            body: body.clone(),
             env: env.clone(),
         }))
     } else {
         Err(MalError::InvalidType(
-            printer::pr_str(&list[1], true),
+            params.clone(),
             "list or vec".to_string(),
-            list[1].type_str(),
         ))
     }
 }
+
+fn head_is(list: &List, name: &str) -> bool {
+    matches!(list.first(), Some(MalVal::Symbol(s)) if s.as_str() == name)
+}
+
+// (try* expr (catch* e handler) (finally* cleanup ...))
+// catch*とfinally*はそれぞれ省略可能
+fn special_try(args: &List, env: &mut Env) -> MalResult {
+    let mut clauses = args.iter();
+    let Some(body) = clauses.next() else {
+        return Err(MalError::WrongArity(
+            "try*".to_string(),
+            Arity::Variadic(1),
+            0,
+        ));
+    };
+
+    let mut catch = None;
+    let mut finally = None;
+    for clause in clauses {
+        let invalid = || MalError::InvalidSyntax(format!("invalid clause in try*: {}", clause));
+        match clause {
+            MalVal::List(l, _) if catch.is_none() && finally.is_none() && head_is(l, "catch*") => {
+                let Some((MalVal::Symbol(sym), handler)) = l.iter().skip(1).collect_tuple() else {
+                    return Err(invalid());
+                };
+                catch = Some((sym.to_string(), handler.clone()))
+            }
+            MalVal::List(l, _) if finally.is_none() && head_is(l, "finally*") => {
+                finally = Some(l.rest())
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    let depth = HANDLERS.with_borrow(Vec::len);
+    if catch.is_some() {
+        HANDLERS.with_borrow_mut(|h| h.push(Handler::Try));
+    }
+    let result = EVAL(body.clone(), env);
+    HANDLERS.with_borrow_mut(|h| h.truncate(depth));
+    let result = match (result, catch) {
+        // 有効なrestart-caseへの巻き戻しは捕捉しない
+        (Err(e), Some((sym, handler))) if !matches!(e, MalError::Restart(_, _)) => {
+            let mut catch_env = Env::new(Some(env));
+            catch_env.set(sym, MalVal::from_error(e.clone()));
+            core::with_caught(e, || EVAL(handler, &mut catch_env))
+        }
+        (result, _) => result,
+    };
+    // finally*は成功時・失敗時のどちらでも評価し、その値は捨てる
+    finally.iter().flatten().try_for_each(|x| {
+        EVAL(x.clone(), env)?;
+        Ok(())
+    })?;
+    result
+}
+
+// コンディションを通知する. ハンドラが正常に戻った場合は外側のハンドラに委ねる
+// エラーの場合は、それを捕捉するtry*より外側のハンドラには通知しない
+fn signal(cond: &MalError, val: MalVal, is_error: bool) -> Result<(), MalError> {
+    let handlers = HANDLERS.take();
+    let result = handlers
+        .iter()
+        .enumerate()
+        .rev()
+        .take_while(|(_, h)| !(is_error && matches!(h, Handler::Try)))
+        .filter_map(|(i, h)| match h {
+            Handler::Bind(ty, handler) if condition_matches(ty, cond) => Some((i, handler)),
+            _ => None,
+        })
+        .try_for_each(|(i, handler)| {
+            // ハンドラの実行中はそれより外側のハンドラだけを有効にする
+            HANDLERS.set(handlers[..i].to_vec());
+            apply_fn(handler, vec![val.clone()]).map(|_| ())
+        });
+    HANDLERS.set(handlers);
+    result
+}
+
+// :errorは全てのコンディションに一致する
+// それ以外はエラーの種類か、ex-infoのデータ (throwされたハッシュマップ) の:typeと比較する
+fn condition_matches(ty: &MalVal, cond: &MalError) -> bool {
+    let MalVal::Keyword(ty) = ty else {
+        return false;
+    };
+    ty.as_str() == "error"
+        || ty.as_str() == cond.kind()
+        || matches!(
+            cond.data(),
+            Some(MalVal::HashMap(m, _)) if m.get(&MalVal::keyword("type")) == Some(&MalVal::Keyword(ty.clone()))
+        )
+}
+
+// (lazy-seq body...)
+// bodyは最初に要素が必要になった時に一度だけ評価する
+fn special_lazy_seq(args: &List, env: &mut Env) -> MalResult {
+    let body = MalVal::List(args.cons(MalVal::symbol("do")), Rc::new(MalVal::Nil));
+    Ok(MalVal::LazySeq(Rc::new(LazySeq::new(MalVal::func(
+        Closure {
+            name: None,
+            rev_params: (vec![], None),
+            body,
+            env: env.clone(),
+        },
+    )))))
+}
+
+// (signal cond)
+fn special_signal(args: &List, env: &mut Env) -> MalResult {
+    let Some((cond,)) = args.iter().collect_tuple() else {
+        return Err(MalError::WrongArity(
+            "signal".to_string(),
+            Arity::Fixed(1),
+            args.len(),
+        ));
+    };
+
+    let val = EVAL(cond.clone(), env)?;
+    signal(&MalError::from_val(val.clone()), val, false)?;
+    Ok(MalVal::Nil)
+}
+
+// (handler-bind [type handler ...] body)
+fn special_handler_bind(args: &List, env: &mut Env) -> MalResult {
+    let Some((bindings, body)) = args.iter().collect_tuple() else {
+        return Err(MalError::WrongArity(
+            "handler-bind".to_string(),
+            Arity::Fixed(2),
+            args.len(),
+        ));
+    };
+
+    let Some(bindings) = bindings.seq_iter() else {
+        return Err(MalError::InvalidType(
+            bindings.clone(),
+            "list or vec".to_string(),
+        ));
+    };
+    if bindings.len() % 2 != 0 {
+        return Err(MalError::InvalidSyntax(
+            "handler-bind requires an even number of forms in bindings".to_string(),
+        ));
+    }
+    let handlers: Vec<_> = bindings
+        .tuples()
+        .map(|(ty, handler)| match ty {
+            MalVal::Keyword(_) => Ok(Handler::Bind(ty.clone(), EVAL(handler.clone(), env)?)),
+            _ => Err(MalError::InvalidType(ty.clone(), "keyword".to_string())),
+        })
+        .try_collect()?;
+
+    let depth = HANDLERS.with_borrow(Vec::len);
+    // 先に書いたハンドラが優先されるように逆順に積む
+    HANDLERS.with_borrow_mut(|h| h.extend(handlers.into_iter().rev()));
+    let result = EVAL(body.clone(), env);
+    HANDLERS.with_borrow_mut(|h| h.truncate(depth));
+    result
+}
+
+// (restart-case expr (:name [params] body) ...)
+fn special_restart_case(args: &List, env: &mut Env) -> MalResult {
+    let mut clauses = args.iter();
+    let Some(expr) = clauses.next() else {
+        return Err(MalError::WrongArity(
+            "restart-case".to_string(),
+            Arity::Variadic(1),
+            0,
+        ));
+    };
+
+    let restarts: Vec<_> = clauses
+        .map(|clause| {
+            let parts = match clause {
+                MalVal::List(l, _) => l.iter().collect_tuple(),
+                _ => None,
+            };
+            match parts {
+                Some((name @ MalVal::Keyword(_), params, body)) => {
+                    Ok((name.clone(), closure(None, params, body, env)?))
+                }
+                _ => Err(MalError::InvalidSyntax(format!(
+                    "invalid clause in restart-case: {}",
+                    clause
+                ))),
+            }
+        })
+        .try_collect()?;
+
+    let names = restarts.iter().map(|(name, _)| name.clone()).collect();
+    match core::with_restarts(names, || EVAL(expr.clone(), env)) {
+        Err(MalError::Restart(name, args)) => {
+            match restarts
+                .iter()
+                .find(|(n, _)| *n == MalVal::keyword(name.as_str()))
+            {
+                Some((_, f)) => apply_fn(f, args),
+                None => Err(MalError::Restart(name, args)),
+            }
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::rep;
+    use im_rc::vector;
+    use rustymal::core;
+    use rustymal::reader;
+    use rustymal::types::{Arity, Frame, MalError, MalVal};
+    use std::rc::Rc;
+
+    #[test]
+    fn test_named_fn() {
+        let mut env = core::env();
+        rep(
+            "(def! f (fn* fact [n] (if (<= n 1) 1 (* n (fact (- n 1))))))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+
+        assert_eq!(rep("(f 5)".to_string(), &mut env).unwrap(), "120");
+        assert_eq!(rep("f".to_string(), &mut env).unwrap(), "#<function fact>");
+        assert_eq!(
+            rep("(f)".to_string(), &mut env).unwrap_err().to_string(),
+            "wrong number of args for fact: expected 1, got 0"
+        );
+        // 仮引数は関数名より優先される
+        assert_eq!(rep("((fn* f [f] f) 3)".to_string(), &mut env).unwrap(), "3");
+    }
+
+    #[test]
+    fn test_persistent_collections() {
+        let mut env = core::env();
+        let mut eval = |s: &str| rep(s.to_string(), &mut env).unwrap();
+
+        eval("(def! v (into [] (map str (range 1000))))");
+        eval("(def! m (zipmap (range 1000) (map str (range 1000))))");
+        let elem = |env: &rustymal::env::Env, name: &str| match env.get(&name).unwrap() {
+            MalVal::Vector(v, _) => v[500].clone(),
+            MalVal::HashMap(m, _) => m[&MalVal::Number(500)].clone(),
+            _ => unreachable!(),
+        };
+        let strong_count = |x: MalVal| match x {
+            MalVal::String(s) => Rc::strong_count(&s) - 1,
+            _ => unreachable!(),
+        };
+        let (x, y) = (elem(&env, "v"), elem(&env, "m"));
+        let (nx, ny) = (strong_count(x), strong_count(y));
+
+        // 更新前のコレクションは変わらない
+        let mut eval = |s: &str| rep(s.to_string(), &mut env).unwrap();
+        assert_eq!(
+            eval("(let* [w (assoc v 0 :x) n (assoc (dissoc m 0) 1 :y)] [(v 0) (w 0) (m 0) (m 1) (n 1) (count n)])"),
+            r#"["0" :x "0" "1" :y 999]"#
+        );
+        eval("(def! w (assoc v 0 :x))");
+        eval("(def! n (assoc m 0 :x))");
+        // 更新していない部分は共有し、要素を複製しない
+        assert_eq!(strong_count(elem(&env, "v")), nx);
+        assert_eq!(strong_count(elem(&env, "m")), ny);
+
+        // 関数は同じ実体の場合だけ等しく、キーにできる
+        assert_eq!(
+            rep(
+                "(let* [f (fn* [] 1)] [(= f f) (= f (fn* [] 1)) (get (assoc {} f 1) f)])"
+                    .to_string(),
+                &mut env
+            )
+            .unwrap(),
+            "[true false 1]"
+        );
+    }
+
+    #[test]
+    fn test_cons_list() {
+        let mut env = core::env();
+        rep(
+            "(def! build (fn* [n acc] (if (= n 0) acc (build (- n 1) (cons n acc)))))".to_string(),
+            &mut env,
+        )
+        .unwrap();
+        rep(
+            "(def! sum (fn* [xs acc] (if (empty? xs) acc (sum (rest xs) (+ acc (first xs))))))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+
+        // 長いリストの生成と走査、解放でスタックが溢れないこと
+        assert_eq!(
+            rep("(sum (build 100000 ()) 0)".to_string(), &mut env).unwrap(),
+            "5000050000"
+        );
+        assert_eq!(
+            rep("(rest (cons 1 [2 3]))".to_string(), &mut env).unwrap(),
+            "(2 3)"
+        );
+        assert_eq!(
+            rep(
+                "(= (cons 1 [2 3]) (conj (list 3) 2 1))".to_string(),
+                &mut env
+            )
+            .unwrap(),
+            "true"
+        );
+    }
+
+    #[test]
+    fn test_transient() {
+        let mut env = core::env();
+        rep(
+            "(def! fill (fn* [t n] (if (= n 0) t (fill (assoc! t n (* n n)) (- n 1)))))"
+                .to_string(),
+            &mut env,
+        )
+        .unwrap();
+        rep("(def! t (transient {}))".to_string(), &mut env).unwrap();
+        rep("(def! m (persistent! (fill t 1000)))".to_string(), &mut env).unwrap();
+
+        assert_eq!(rep("(count m)".to_string(), &mut env).unwrap(), "1000");
+        assert_eq!(rep("(get m 12)".to_string(), &mut env).unwrap(), "144");
+        assert_eq!(
+            rep(
+                "(let* [t (conj! (transient [:a]) :b)] [(count t) (get t 1) (contains? t 2)])"
+                    .to_string(),
+                &mut env
+            )
+            .unwrap(),
+            "[2 :b false]"
+        );
+        assert_eq!(
+            rep("(assoc! t 0 0)".to_string(), &mut env)
+                .unwrap_err()
+                .untraced(),
+            &MalError::PersistedTransient
+        );
+        // キーの遅延シーケンスが同じtransientを書き換えても二重に借用しない
+        assert_eq!(
+            rep(
+                "(let* [t (transient {}) m (persistent! (assoc! t (lazy-seq (assoc! t :a 1) nil) 2))] [(get m :a) (count m)])"
+                    .to_string(),
+                &mut env
+            )
+            .unwrap(),
+            "[1 2]"
+        );
+        assert_eq!(
+            rep(
+                "(persistent! (disj! (conj! (transient #{}) 1 2 3) 2))".to_string(),
+                &mut env
+            )
+            .unwrap(),
+            "#{1 3}"
+        );
+        assert_eq!(
+            rep(
+                "(let* [v [1 2] t (conj! (transient v) 3)] [v (persistent! t)])".to_string(),
+                &mut env
+            )
+            .unwrap(),
+            "[[1 2] [1 2 3]]"
+        );
+    }
+
+    #[test]
+    fn test_hash_set() {
+        let mut env = core::env();
+        let eval = |input: &str, env: &mut _| rep(input.to_string(), env).unwrap();
+
+        rep("(def! x 2)".to_string(), &mut env).unwrap();
+        assert_eq!(
+            eval("(= #{1 x (+ x 1)} (hash-set 1 2 3))", &mut env),
+            "true"
+        );
+        assert_eq!(
+            eval("(= (set [1 2 2 3]) (conj #{1} 3 2))", &mut env),
+            "true"
+        );
+        assert_eq!(eval("(set? (set {:a 1}))", &mut env), "true");
+        assert_eq!(eval("(disj #{1 2} 1 3)", &mut env), "#{2}");
+        assert_eq!(eval("(contains? #{nil} nil)", &mut env), "true");
+        assert_eq!(eval("(get #{:a} :b :none)", &mut env), ":none");
+        assert_eq!(
+            eval("(= (union #{1} nil #{2 3}) #{1 2 3})", &mut env),
+            "true"
+        );
+        assert_eq!(
+            eval("(intersection #{1 2 3} #{2 3} #{3 4})", &mut env),
+            "#{3}"
+        );
+        assert_eq!(eval("(difference #{1 2 3} #{2} #{3})", &mut env), "#{1}");
+        assert_eq!(eval("(subset? #{1} #{1 2})", &mut env), "true");
+        assert_eq!(eval("(superset? #{1} #{1 2})", &mut env), "false");
+    }
+
+    #[test]
+    fn test_sorted_collections() {
+        let mut env = core::env();
+        let eval = |input: &str, env: &mut _| rep(input.to_string(), env).unwrap();
+
+        assert_eq!(
+            eval("(sorted-map :b 2 1 :x \"s\" 0 nil 3 :a 1)", &mut env),
+            "{nil 3 1 :x \"s\" 0 :a 1 :b 2}"
+        );
+        assert_eq!(
+            eval("(assoc (sorted-map 2 :b) 1 :a)", &mut env),
+            "{1 :a 2 :b}"
+        );
+        assert_eq!(eval("(= (sorted-map 1 2) {1 2})", &mut env), "true");
+        assert_eq!(eval("(= (sorted-set 1 2) #{2 1})", &mut env), "true");
+        assert_eq!(eval("(conj (sorted-set 3 1) 2 1)", &mut env), "#{1 2 3}");
+        assert_eq!(
+            eval(
+                "(sorted-map-by (fn* [a b] (> a b)) 1 :a 3 :c 2 :b)",
+                &mut env
+            ),
+            "{3 :c 2 :b 1 :a}"
+        );
+        // 順序は等価性と一致する: 表示が同じでも等しくないエラーは区別する
+        assert_eq!(
+            eval(
+                "(let* [a (try* (throw (ex-info \"x\" {:a 1})) (catch* e e))
+                        b (try* (throw (ex-info \"x\" {:a 2})) (catch* e e))
+                        f (fn* [] 1)]
+                   [(count (sorted-set a b a)) (compare f f)])",
+                &mut env
+            ),
+            "[2 0]"
+        );
+        // キーと値が揃わない場合はhash-mapと同じエラー
+        for f in ["hash-map", "sorted-map", "sorted-map-by compare"] {
+            assert_eq!(
+                rep(format!("({} 1 2 3)", f), &mut env)
+                    .unwrap_err()
+                    .untraced()
+                    .kind(),
+                "invalid-syntax"
+            );
+        }
+
+        rep("(def! s (sorted-set 1 2 3 4 5))".to_string(), &mut env).unwrap();
+        assert_eq!(eval("(subseq s > 2)", &mut env), "(3 4 5)");
+        assert_eq!(eval("(subseq s >= 2 < 4)", &mut env), "(2 3)");
+        assert_eq!(eval("(rsubseq s <= 3)", &mut env), "(3 2 1)");
+        assert_eq!(
+            eval("(subseq (sorted-map :a 1 :b 2 :c 3) > :a)", &mut env),
+            "([:b 2] [:c 3])"
+        );
+        assert_eq!(
+            rep("(subseq s > 2 <)".to_string(), &mut env)
+                .unwrap_err()
+                .to_string(),
+            "wrong number of args for subseq: expected 3 or 5, got 4"
+        );
+    }
+
+    #[test]
+    fn test_callable_collections() {
+        let mut env = core::env();
+        let eval = |input: &str, env: &mut _| rep(input.to_string(), env).unwrap();
+
+        assert_eq!(eval("({:a 1} :a)", &mut env), "1");
+        assert_eq!(eval("({:a 1} :b :none)", &mut env), ":none");
+        assert_eq!(eval("(:a {:a 1})", &mut env), "1");
+        assert_eq!(eval("(:b {:a 1} 2)", &mut env), "2");
+        assert_eq!(eval("(#{1 2} 2)", &mut env), "2");
+        assert_eq!(eval("([:x :y] 1)", &mut env), ":y");
+        assert_eq!(eval("((sorted-map 1 :a) 1)", &mut env), ":a");
+        // 末尾位置での呼び出し
+        assert_eq!(eval("((fn* [m] (m :a)) {:a 3})", &mut env), "3");
+        assert_eq!(eval("(map :a [{:a 1} {:a 2} {}])", &mut env), "(1 2 nil)");
+        assert_eq!(eval("(map [10 20 30] (list 2 0))", &mut env), "(30 10)");
+        assert_eq!(eval("(apply {:a 1} [:b 0])", &mut env), "0");
+        assert_eq!(eval("(apply + 1 2 [3 4])", &mut env), "10");
+        assert_eq!(eval("(map + [1 2 3] [10 20])", &mut env), "(11 22)");
+        assert_eq!(
+            rep("([1] 3)".to_string(), &mut env).unwrap_err().untraced(),
+            &MalError::IndexOutOfBounds(3, 1)
+        );
+    }
+
+    #[test]
+    fn test_bigint() {
+        let mut env = core::env();
+        let eval = |input: &str, env: &mut _| rep(input.to_string(), env).unwrap();
+
+        assert_eq!(
+            eval("(* 9223372036854775807 2)", &mut env),
+            "18446744073709551614"
+        );
+        assert_eq!(
+            eval("(+ 1 9223372036854775807)", &mut env),
+            "9223372036854775808"
+        );
+        assert_eq!(
+            eval("(/ -9223372036854775808 -1)", &mut env),
+            "9223372036854775808"
+        );
+        // i64に収まる結果はNumberに戻る
+        assert_eq!(
+            eval("(- 100000000000000000000 99999999999999999999)", &mut env),
+            "1"
+        );
+        assert_eq!(
+            eval(
+                "(= 1 (- 100000000000000000001 100000000000000000000))",
+                &mut env
+            ),
+            "true"
+        );
+        assert_eq!(
+            eval(
+                "(< 1 100000000000000000000 100000000000000000001)",
+                &mut env
+            ),
+            "true"
+        );
+        assert_eq!(
+            eval(
+                "(get {18446744073709551616 :big} (* 4294967296 4294967296))",
+                &mut env
+            ),
+            ":big"
+        );
+        assert_eq!(
+            rep("(/ 100000000000000000000 0)".to_string(), &mut env)
+                .unwrap_err()
+                .untraced(),
+            &MalError::DividedByZero
+        );
+    }
+
+    #[test]
+    fn test_float() {
+        let mut env = core::env();
+        let eval = |input: &str, env: &mut _| rep(input.to_string(), env).unwrap();
+
+        assert_eq!(eval("(/ (+ 1 2 3 4) 4.0)", &mut env), "2.5");
+        assert_eq!(eval("(* 2 1.5)", &mut env), "3.0");
+        assert_eq!(eval("1e-3", &mut env), "0.001");
+        assert_eq!(
+            eval("[##Inf ##-Inf ##NaN]", &mut env),
+            "[##Inf ##-Inf ##NaN]"
+        );
+        assert_eq!(eval("(/ 1.0 0)", &mut env), "##Inf");
+        assert_eq!(eval("(+ 100000000000000000000 0.5)", &mut env), "1e20");
+        assert_eq!(eval("(< 1 1.5 2)", &mut env), "true");
+        assert_eq!(eval("(< 1 ##NaN)", &mut env), "false");
+        assert_eq!(eval("(= 1 1.0)", &mut env), "false");
+        assert_eq!(eval("(= ##NaN ##NaN)", &mut env), "true");
+        assert_eq!(
+            eval("[(int? 1) (float? 1.0) (int? 1.0)]", &mut env),
+            "[true true false]"
+        );
+        assert_eq!(
+            eval("[(double 3) (long -2.7) (long 1e19)]", &mut env),
+            "[3.0 -2 10000000000000000000]"
+        );
+        assert_eq!(
+            rep("(+ 1.5 :a)".to_string(), &mut env)
+                .unwrap_err()
+                .untraced(),
+            &MalError::InvalidType(MalVal::keyword("a"), "number".to_string())
+        );
+    }
+
+    #[test]
+    fn test_ratio() {
+        let mut env = core::env();
+        let eval = |input: &str, env: &mut _| rep(input.to_string(), env).unwrap();
+
+        assert_eq!(eval("(/ 1 3)", &mut env), "1/3");
+        assert_eq!(eval("(/ 6 4)", &mut env), "3/2");
+        assert_eq!(eval("(/ 6 3)", &mut env), "2");
+        assert_eq!(eval("2/4", &mut env), "1/2");
+        assert_eq!(eval("(+ 1/3 1/3 1/3)", &mut env), "1");
+        assert_eq!(eval("(* 3 -1/6)", &mut env), "-1/2");
+        assert_eq!(eval("(+ 1/2 0.25)", &mut env), "0.75");
+        assert_eq!(eval("(< 1/3 0.34 1/2)", &mut env), "true");
+        assert_eq!(eval("(= 1/2 (/ 2 4))", &mut env), "true");
+        assert_eq!(
+            eval("[(numerator 6/4) (denominator 6/4)]", &mut env),
+            "[3 2]"
+        );
+        assert_eq!(eval("(rationalize 0.1)", &mut env), "1/10");
+        assert_eq!(eval("(rationalize 1.5e-7)", &mut env), "3/20000000");
+        assert_eq!(eval("(rationalize 2.5e3)", &mut env), "2500");
+        assert_eq!(eval("(long -7/2)", &mut env), "-3");
+        assert_eq!(
+            rep("(/ 1/2 0)".to_string(), &mut env)
+                .unwrap_err()
+                .untraced(),
+            &MalError::DividedByZero
+        );
+    }
+
+    #[test]
+    fn test_math() {
+        let mut env = core::env();
+        let eval = |input: &str, env: &mut _| rep(input.to_string(), env).unwrap();
+
+        assert_eq!(
+            eval("[(quot -7 2) (rem -7 2) (mod -7 2) (mod 7 -2)]", &mut env),
+            "[-3 -1 1 -1]"
+        );
+        assert_eq!(eval("(mod 7/2 1)", &mut env), "1/2");
+        assert_eq!(
+            eval("(inc 9223372036854775807)", &mut env),
+            "9223372036854775808"
+        );
+        assert_eq!(
+            eval("(abs -9223372036854775808)", &mut env),
+            "9223372036854775808"
+        );
+        assert_eq!(eval("[(min 3 1.5 2) (max 1 3/2)]", &mut env), "[1.5 3/2]");
+        assert_eq!(eval("[(pow 2 -2) (pow 4 0.5)]", &mut env), "[1/4 2.0]");
+        assert_eq!(
+            eval("[(bit-and 12 10) (bit-or 12 10) (bit-xor 12 10)]", &mut env),
+            "[8 14 6]"
+        );
+        assert_eq!(
+            eval("[(bit-shift-left 1 64) (bit-shift-right -9 1)]", &mut env),
+            "[18446744073709551616 -5]"
+        );
+        assert_eq!(
+            eval(
+                "[(zero? 0.0) (pos? -1/2) (neg? -1) (even? 0) (odd? -3)]",
+                &mut env
+            ),
+            "[true false true true true]"
+        );
+        // 浮動小数点数でも0で割るとエラー. /は##Infを返す
+        for input in ["(mod 1 0)", "(quot 1.0 0)", "(rem 1 0.0)", "(mod 1.5 -0.0)"] {
+            assert_eq!(
+                rep(input.to_string(), &mut env).unwrap_err().untraced(),
+                &MalError::DividedByZero
+            );
+        }
+        assert_eq!(eval("(/ 1.0 0)", &mut env), "##Inf");
+        assert_eq!(
+            rep("(bit-shift-left 1 100000000)".to_string(), &mut env)
+                .unwrap_err()
+                .untraced(),
+            &MalError::Overflow("bit-shift-left".to_string())
+        );
+    }
+
+    #[test]
+    fn test_compare_and_sort() {
+        let mut env = core::env();
+        let eval = |input: &str, env: &mut _| rep(input.to_string(), env).unwrap();
+
+        assert_eq!(eval("(< \"a\" \"b\" \"c\")", &mut env), "true");
+        assert_eq!(eval("(>= :b :a :a)", &mut env), "true");
+        assert_eq!(eval("(< [1 2] [1 3])", &mut env), "true");
+        assert_eq!(
+            eval("[(compare 1 2) (compare :b :a) (compare 1 1.0)]", &mut env),
+            "[-1 1 0]"
+        );
+        assert_eq!(
+            rep("(< 1 \"a\")".to_string(), &mut env)
+                .unwrap_err()
+                .untraced(),
+            &MalError::InvalidType(MalVal::string("a"), "number".to_string())
+        );
+        assert_eq!(eval("(sort [3 1.5 2])", &mut env), "(1.5 2 3)");
+        // コレクションの要素の数値も値で比較する
+        assert_eq!(
+            eval(
+                "[(< [1] [1.0]) (<= [1] [1.0]) (compare [1] [1.0])]",
+                &mut env
+            ),
+            "[false true 0]"
+        );
+        assert_eq!(eval("(sort [[1.0] [1]])", &mut env), "([1.0] [1])");
+        assert_eq!(eval("(sort [[1] [1.0]])", &mut env), "([1] [1.0])");
+        assert_eq!(eval("(sort > [3 1 2])", &mut env), "(3 2 1)");
+        assert_eq!(
+            eval("(sort (fn* [a b] (- b a)) [3 1 2])", &mut env),
+            "(3 2 1)"
+        );
+        // 安定ソート
+        assert_eq!(
+            eval("(sort-by first [[2 :a] [1 :b] [2 :c]])", &mut env),
+            "([1 :b] [2 :a] [2 :c])"
+        );
+        assert_eq!(
+            eval("(sort-by first > [[1 :a] [2 :b]])", &mut env),
+            "([2 :b] [1 :a])"
+        );
+        assert_eq!(eval("(max-key count [1] [1 2] [3 4])", &mut env), "[3 4]");
+        assert_eq!(eval("(min-key count [1 2] [1] [2])", &mut env), "[2]");
+    }
+
+    #[test]
+    fn test_string_library() {
+        let mut env = core::env();
+        let eval = |input: &str, env: &mut _| rep(input.to_string(), env).unwrap();
+
+        assert_eq!(eval("(count \"日本語\")", &mut env), "3");
+        assert_eq!(eval("(subs \"日本語です\" 1 3)", &mut env), "\"本語\"");
+        assert_eq!(
+            eval("(split \"a,b,,c,,\" \",\")", &mut env),
+            "[\"a\" \"b\" \"\" \"c\"]"
+        );
+        assert_eq!(eval("(join \", \" [1 \"a\" :k])", &mut env), "\"1, a, :k\"");
+        assert_eq!(
+            eval("[(trim \" x \") (triml \" x \") (trimr \" x \")]", &mut env),
+            "[\"x\" \"x \" \" x\"]"
+        );
+        assert_eq!(eval("(upper-case \"straße\")", &mut env), "\"STRASSE\"");
+        assert_eq!(
+            eval(
+                "[(starts-with? \"hello\" \"he\") (ends-with? \"hello\" \"lo\") (includes? \"hello\" \"z\")]",
+                &mut env
+            ),
+            "[true true false]"
+        );
+        assert_eq!(eval("(index-of \"日本語日本\" \"本\" 2)", &mut env), "4");
+        assert_eq!(
+            eval("(replace \"a-b-c\" \"-\" \"+\")", &mut env),
+            "\"a+b+c\""
+        );
+        assert_eq!(eval("(reverse \"日本語\")", &mut env), "\"語本日\"");
+        assert_eq!(
+            eval("[(blank? nil) (blank? \" \") (blank? \"a\")]", &mut env),
+            "[true true false]"
+        );
+        assert_eq!(
+            rep("(subs \"abc\" 4)".to_string(), &mut env)
+                .unwrap_err()
+                .untraced(),
+            &MalError::IndexOutOfBounds(4, 3)
+        );
+    }
+
+    #[test]
+    fn test_regex() {
+        let mut env = core::env();
+        let eval = |input: &str, env: &mut _| rep(input.to_string(), env).unwrap();
+
+        assert_eq!(eval(r#"#"\d+""#, &mut env), r#"#"\d+""#);
+        assert_eq!(eval(r#"(re-pattern "a\"b")"#, &mut env), r#"#"a\"b""#);
+        assert_eq!(eval(r#"(= #"a+" (re-pattern "a+"))"#, &mut env), "true");
+        assert_eq!(
+            eval(r#"(re-find #"\d+" "abc123def45")"#, &mut env),
+            r#""123""#
+        );
+        assert_eq!(
+            eval(r#"(re-find #"(\w)(\d)?" "a")"#, &mut env),
+            r#"["a" "a" nil]"#
+        );
+        assert_eq!(eval(r#"(re-matches #"a|ab" "ab")"#, &mut env), r#""ab""#);
+        assert_eq!(eval(r#"(re-matches #"\d+" "12a")"#, &mut env), "nil");
+        assert_eq!(eval(r#"(re-seq #"\d" "a1b2")"#, &mut env), r#"("1" "2")"#);
+        assert_eq!(eval(r#"(re-groups #"\d" "a1")"#, &mut env), r#"["1"]"#);
+        assert_eq!(
+            eval(r#"(split "a1b22c" #"\d+")"#, &mut env),
+            r#"["a" "b" "c"]"#
+        );
+        assert_eq!(
+            eval(r#"(replace "a1b2" #"(\d)" "<$1>")"#, &mut env),
+            r#""a<1>b<2>""#
+        );
+        assert_eq!(
+            eval(r#"(replace "a1b2" #"\d" (fn* [m] (str m m)))"#, &mut env),
+            r#""a11b22""#
+        );
+        assert_eq!(
+            rep(r#"(re-pattern "(")"#.to_string(), &mut env)
+                .unwrap_err()
+                .kind(),
+            "invalid-regex"
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        let mut env = core::env();
+        let eval = |input: &str, env: &mut _| rep(input.to_string(), env).unwrap();
+
+        assert_eq!(
+            eval(
+                r#"(format "%s|%S|%d|%x|%.2f|%%" "a" "a" 42 255 3.14159)"#,
+                &mut env
+            ),
+            r#""a|\"a\"|42|ff|3.14|%""#
+        );
+        assert_eq!(
+            eval(r#"(format "[%-6s][%6s][%05d]" "ab" "ab" -42)"#, &mut env),
+            r#""[ab    ][    ab][-0042]""#
+        );
+        assert_eq!(
+            rep(r#"(format "%s %s" 1)"#.to_string(), &mut env)
+                .unwrap_err()
+                .untraced(),
+            &MalError::WrongArity("format".to_string(), Arity::Fixed(3), 2)
+        );
+        // 遅延シーケンスの計算で起きたエラーは伝わる
+        assert_eq!(
+            rep(
+                r#"(format "%s" (map (fn* [x] (throw x)) [:boom]))"#.to_string(),
+                &mut env
+            )
+            .unwrap_err()
+            .untraced(),
+            &MalError::Throw(MalVal::keyword("boom"))
+        );
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let mut env = core::env();
+        let eval = |input: &str, env: &mut _| rep(input.to_string(), env).unwrap();
+
+        eval(r#"(def! name "Bob")"#, &mut env);
+        eval("(def! items [1 2 3])", &mut env);
+        assert_eq!(
+            eval(
+                r#"#f"Hello ~{name}, you have ~{(count items)} items""#,
+                &mut env
+            ),
+            r#""Hello Bob, you have 3 items""#
+        );
+        // ~{}の中の文字列と{}、エスケープされた~
+        assert_eq!(
+            eval(r#"#f"~{(get {:a "}"} :a)}\n\~{x}""#, &mut env),
+            r#""}\n~{x}""#
+        );
+        assert_eq!(
+            rep(r#"#f"a ~{(count items} b""#.to_string(), &mut env)
+                .unwrap_err()
+                .report(),
+            "invalid syntax: broken interpolation ~{(count items}\ncaused by: expected ), got EOF"
+        );
+    }
+
+    #[test]
+    fn test_seq() {
+        let mut env = core::env();
+        let eval = |input: &str, env: &mut _| rep(input.to_string(), env).unwrap();
+
+        assert_eq!(
+            eval(r#"[(seq []) (seq "ab") (seq {:a 1})]"#, &mut env),
+            r#"[nil ("a" "b") ([:a 1])]"#
+        );
+        assert_eq!(
+            eval(r#"[(empty? nil) (empty? "") (count #{1 2})]"#, &mut env),
+            "[true true 2]"
+        );
+        assert_eq!(
+            eval(
+                r#"[(first "abc") (rest #{1}) (next [1]) (next [1 2])]"#,
+                &mut env
+            ),
+            r#"["a" () nil (2)]"#
+        );
+        assert_eq!(
+            eval("[(nth [1 2 3] 1) (nth (list 1 2) 5 :none)]", &mut env),
+            "[2 :none]"
+        );
+        assert_eq!(
+            eval(
+                "[(last (list 1 2 3)) (butlast [1 2 3]) (butlast [1])]",
+                &mut env
+            ),
+            "[3 (1 2) nil]"
+        );
+        assert_eq!(
+            eval(r#"[(take 2 "abcd") (drop 2 (list 1 2 3))]"#, &mut env),
+            r#"[("a" "b") (3)]"#
+        );
+        assert_eq!(eval("(drop 9223372036854775807 (list 1))", &mut env), "()");
+        assert_eq!(eval("(reverse [1 2 3])", &mut env), "(3 2 1)");
+        assert_eq!(
+            eval("[(into [] (list 1 2)) (into (list) [1 2])]", &mut env),
+            "[[1 2] (2 1)]"
+        );
+        assert_eq!(eval("(into {} [[:a 1]])", &mut env), "{:a 1}");
+        assert_eq!(
+            rep("(nth [1 2 3] 3)".to_string(), &mut env)
+                .unwrap_err()
+                .untraced(),
+            &MalError::IndexOutOfBounds(3, 3)
+        );
+    }
+
+    #[test]
+    fn test_lazy_seq() {
+        let mut env = core::env();
+        let eval = |input: &str, env: &mut _| rep(input.to_string(), env).unwrap();
+
+        assert_eq!(
+            eval("[(take 3 (range)) (range 1 6 2) (range 3 0 -1)]", &mut env),
+            "[(0 1 2) (1 3 5) (3 2 1)]"
+        );
+        assert_eq!(
+            eval(
+                "[(take 3 (iterate inc 1)) (repeat 2 :a) (take 3 (cycle [1 2]))]",
+                &mut env
+            ),
+            "[(1 2 3) (:a :a) (1 2 1)]"
+        );
+        assert_eq!(
+            eval(
+                "(take 3 (filter even? (map inc (drop-while neg? (range -5 100)))))",
+                &mut env
+            ),
+            "(2 4 6)"
+        );
+        assert_eq!(
+            eval("(take-while (fn* [x] (< x 3)) (range))", &mut env),
+            "(0 1 2)"
+        );
+        eval(
+            "(def! nat (fn* [n] (lazy-seq (cons n (nat (inc n))))))",
+            &mut env,
+        );
+        assert_eq!(eval("(nth (nat 0) 10000)", &mut env), "10000");
+        assert_eq!(eval("(= (range 3) [0 1 2])", &mut env), "true");
+        assert!(eval("(pr-str (range 150))", &mut env).ends_with(r#"148 149)""#));
+
+        // REPLの表示は制限がなくても打ち切る
+        assert!(eval("(range)", &mut env).ends_with(" 98 99 ...)"));
+        eval("(set-print-length! 3)", &mut env);
+        assert_eq!(eval("(range)", &mut env), "(0 1 2 ...)");
+        eval("(set-print-length! nil)", &mut env);
+
+        // 要素の計算で起きたエラーは表示する時に伝わる
+        let err = rep("(map (fn* [x] (throw x)) [:boom])".to_string(), &mut env).unwrap_err();
+        assert_eq!(err.untraced(), &MalError::Throw(MalVal::keyword("boom")));
+        // 比較では計算に失敗した要素も比べる
+        assert_eq!(
+            eval(
+                "(let* [bad (map (fn* [x] (throw x)) [:boom])] [(= bad bad) (= bad ()) (compare bad ())])",
+                &mut env
+            ),
+            "[true false 1]"
+        );
+    }
+
+    #[test]
+    fn test_higher_order() {
+        let mut env = core::env();
+        let eval = |input: &str, env: &mut _| rep(input.to_string(), env).unwrap();
+
+        assert_eq!(
+            eval(
+                "[(reduce + (range 5)) (reduce + 10 [1 2]) (reduce-kv (fn* [a k v] (+ a k v)) 0 [10 20])]",
+                &mut env
+            ),
+            "[10 13 31]"
+        );
+        assert_eq!(
+            eval(
+                "(reduce (fn* [a x] (if (> x 3) (reduced a) (+ a x))) (range))",
+                &mut env
+            ),
+            "6"
+        );
+        assert_eq!(
+            eval("[(some even? [1 4]) (every? odd? [1 2])]", &mut env),
+            "[true false]"
+        );
+        assert_eq!(
+            eval(
+                "[(remove even? (range 5)) (keep (fn* [x] (if (odd? x) x nil)) (range 5))]",
+                &mut env
+            ),
+            "[(1 3) (1 3)]"
+        );
+        assert_eq!(
+            eval(
+                "[(partition 2 (range 5)) (partition 2 2 [:x] (range 3)) (partition-by odd? [1 3 2])]",
+                &mut env
+            ),
+            "[((0 1) (2 3)) ((0 1) (2 :x)) ((1 3) (2))]"
+        );
+        assert_eq!(
+            eval(
+                "[(group-by odd? [1 2 3]) (frequencies [:a :a]) (zipmap [:a] (range))]",
+                &mut env
+            ),
+            "[{true [1 3] false [2]} {:a 2} {:a 0}]"
+        );
+        assert_eq!(
+            eval(
+                "[(mapcat reverse [[1 2] [3]]) (distinct [1 2 1]) (interleave [1 2] [:a :b])]",
+                &mut env
+            ),
+            "[(2 1 3) (1 2) (1 :a 2 :b)]"
+        );
+        assert_eq!(
+            eval(
+                "[((comp str +) 1 2) ((partial + 1) 2) ((juxt inc dec) 1) ((complement even?) 1) ((constantly 7) 0) (identity 4)]",
+                &mut env
+            ),
+            r#"["3" 3 [2 0] true 7 4]"#
+        );
+        // 初期値がなければ (f) から始めるので、+と*は引数なしで単位元を返す
+        assert_eq!(
+            eval(
+                "[(reduce + []) (reduce * []) (transduce (map inc) + [])]",
+                &mut env
+            ),
+            "[0 1 0]"
+        );
+        assert_eq!(eval("(comp inc dec)", &mut env), "#<function comp>");
+    }
+
+    #[test]
+    fn test_transducer() {
+        let mut env = core::env();
+        let eval = |input: &str, env: &mut _| rep(input.to_string(), env).unwrap();
+
+        assert_eq!(
+            eval(
+                "(transduce (comp (filter odd?) (map inc)) + 0 (range 10))",
+                &mut env
+            ),
+            "30"
+        );
+        assert_eq!(
+            eval(
+                "(into [] (comp (map inc) (take 3) (partition-all 2)) (range))",
+                &mut env
+            ),
+            "[[1 2] [3]]"
+        );
+        assert_eq!(
+            eval("(take 3 (sequence (map inc) (range)))", &mut env),
+            "(1 2 3)"
+        );
+        assert_eq!(
+            eval("(eduction (map inc) (filter odd?) (range 6))", &mut env),
+            "(1 3 5)"
+        );
+        assert_eq!(eval("(partition-all 2 [1 2 3])", &mut env), "((1 2) (3))");
+        assert_eq!(
+            eval(
+                "[(transduce (map inc) + [1 2 3]) (transduce (partition-all 2) conj [] [1 2 3])]",
+                &mut env
+            ),
+            "[9 [[1 2] [3]]]"
+        );
+        assert_eq!(
+            eval(
+                "(transduce (map inc) (completing (fn* [a x] (+ a x)) str) 0 [1 2])",
+                &mut env
+            ),
+            r#""5""#
+        );
+    }
+
+    #[test]
+    fn test_nested_data() {
+        let mut env = core::env();
+        let eval = |input: &str, env: &mut _| rep(input.to_string(), env).unwrap();
+
+        eval("(def! m {:a {:b [1 {:c 2}]}})", &mut env);
+        assert_eq!(
+            eval(
+                "[(get-in m [:a :b 1 :c]) (get-in m [:a :x] :none)]",
+                &mut env
+            ),
+            "[2 :none]"
+        );
+        assert_eq!(
+            eval(
+                "[(assoc-in m [:a :b 0] 9) (assoc-in nil [:x :y] 1)]",
+                &mut env
+            ),
+            "[{:a {:b [9 {:c 2}]}} {:x {:y 1}}]"
+        );
+        assert_eq!(
+            eval(
+                "[(update [1 2] 1 + 10) (update-in m [:a :b 1 :c] inc) (dissoc-in m [:a :b 0])]",
+                &mut env
+            ),
+            "[[1 12] {:a {:b [1 {:c 3}]}} {:a {:b [{:c 2}]}}]"
+        );
+        assert_eq!(
+            eval(
+                "[(merge {:a 1} nil {:a 2}) (merge-with + {:a 1} {:a 2 :b 3})]",
+                &mut env
+            ),
+            "[{:a 2} {:a 3 :b 3}]"
+        );
+        assert_eq!(
+            eval(
+                "[(select-keys [:x :y] [1]) (rename-keys {:a 1} {:a :b})]",
+                &mut env
+            ),
+            "[{1 :y} {:b 1}]"
+        );
+        assert_eq!(
+            eval(
+                "[(find {:a 1} :a) (find {:a 1} :b) (key (first {:a 1})) (val (find [:x] 0))]",
+                &mut env
+            ),
+            "[[:a 1] nil :a :x]"
+        );
+        assert_eq!(
+            eval("[(empty [1]) (empty {:a 1}) (empty (range 3))]", &mut env),
+            "[[] {} ()]"
+        );
+    }
+
+    #[test]
+    fn test_call_stack_trace() {
+        let mut env = core::env();
+        rep("(def! g (fn* inner [x] (+ x :a)))".to_string(), &mut env).unwrap();
+        rep("(def! f (fn* outer [x] (* 2 (g x))))".to_string(), &mut env).unwrap();
+
+        let err = rep("(f 1)".to_string(), &mut env).unwrap_err();
+        assert_eq!(
+            err.report(),
+            "expected number for :a, got keyword\n  at inner (g x)\n  at outer (f 1)"
+        );
+        let frame = |name: &str, form: &str| {
+            Frame {
+                name: Some(name.into()),
+                form: reader::read_str(form.to_string()).unwrap(),
+            }
+            .to_mal()
+        };
+        assert_eq!(
+            err.trace(),
+            MalVal::vec(vector![frame("inner", "(g x)"), frame("outer", "(f 1)")])
+        );
+
+        // 組み込み関数から呼ばれた関数もスタックに残る
+        let err = rep("(first (map g [1]))".to_string(), &mut env).unwrap_err();
+        assert_eq!(
+            err.report(),
+            "expected number for :a, got keyword\n  at inner (#<function inner> 1)"
+        );
+        // throwした値のスタックは引数なしのex-traceで取り出す
+        rep("(def! h (fn* thrower [] (throw 5)))".to_string(), &mut env).unwrap();
+        assert_eq!(
+            rep("(try* (h) (catch* e [e (ex-trace)]))".to_string(), &mut env).unwrap(),
+            r#"[5 [{:form (h) :name "thrower"}]]"#
+        );
+    }
+
+    #[test]
+    fn test_try_ex_info() {
+        let mut env = core::env();
+        let mut eval = |s: &str| rep(s.to_string(), &mut env);
+
+        eval("(def! e (ex-info \"boom\" {:id 1} (ex-info \"root\" {})))").unwrap();
+        assert_eq!(
+            eval("(try* (throw e) (catch* x (ex-data x)))").unwrap(),
+            "{:id 1}"
+        );
+        assert_eq!(eval("(ex-message (ex-cause e))").unwrap(), "\"root\"");
+        assert_eq!(
+            eval(
+                "(= (try* (+ 1 :a) (catch* x (ex-data x)))
+                    {:type :invalid-type :message \"expected number for :a, got keyword\"
+                     :value :a :expected \"number\" :got \"keyword\"})"
+            )
+            .unwrap(),
+            "true"
+        );
+
+        // finally*は成功時も失敗時も評価される
+        assert_eq!(
+            eval("(try* 1 (finally* (throw :fin)))")
+                .unwrap_err()
+                .untraced(),
+            &MalError::Throw(MalVal::keyword("fin"))
+        );
+        assert_eq!(
+            eval("(try* (throw 2) (catch* x x) (finally* (throw :fin)))")
+                .unwrap_err()
+                .untraced(),
+            &MalError::Throw(MalVal::keyword("fin"))
+        );
+        assert_eq!(eval("(try* 1 (finally* 2))").unwrap(), "1");
+    }
+
+    #[test]
+    fn test_restart() {
+        let mut env = core::env();
+        let mut eval = |s: &str| rep(s.to_string(), &mut env);
+
+        eval(
+            "(def! process (fn* [x]
+               (restart-case
+                 (if (= x 2) (throw (ex-info \"bad\" {:type :bad-record})) (* x 10))
+                 (:skip [] nil)
+                 (:use-value [v] v))))",
+        )
+        .unwrap();
+        assert_eq!(
+            eval(
+                "(handler-bind [:bad-record (fn* [c] (invoke-restart :use-value -1))]
+                   (list (process 1) (process 2) (process 3)))"
+            )
+            .unwrap(),
+            "(10 -1 30)"
+        );
+        assert_eq!(
+            eval("(handler-bind [:error (fn* [c] (invoke-restart :skip))] (list (process 2)))")
+                .unwrap(),
+            "(nil)"
+        );
+        // ハンドラが正常に戻った場合はエラーがそのまま伝播する
+        assert_eq!(
+            eval("(handler-bind [:error (fn* [c] nil)] (process 2))")
+                .unwrap_err()
+                .to_string(),
+            "bad"
+        );
+        assert_eq!(eval("(signal :unhandled)").unwrap(), "nil");
+
+        // try*で捕捉されるエラーはその外側のハンドラに通知しない
+        eval("(def! h (fn* [c] (throw :handler)))").unwrap();
+        assert_eq!(
+            eval("(handler-bind [:error h] (try* (throw :inner) (catch* e e)))").unwrap(),
+            ":inner"
+        );
+        assert_eq!(
+            eval("(try* (handler-bind [:error h] (throw :inner)) (catch* e e))").unwrap(),
+            ":handler"
+        );
+        assert_eq!(
+            eval("(handler-bind [:error h] (try* (throw :inner) (catch* e (throw e))))")
+                .unwrap_err()
+                .to_string(),
+            ":handler"
+        );
+
+        // 有効なrestart-caseがなければ通常のエラーとして捕捉できる
+        assert_eq!(
+            eval("(try* (invoke-restart :nope) (catch* e (:type (ex-data e))))").unwrap(),
+            ":control-error"
+        );
+        assert_eq!(
+            eval("(restart-case (try* (invoke-restart :skip) (catch* e :caught)) (:skip [] :skipped))")
+                .unwrap(),
+            ":skipped"
+        );
+        assert_eq!(
+            eval("(restart-case (try* (invoke-restart :nope) (catch* e :caught)) (:skip [] :skipped))")
+                .unwrap(),
+            ":caught"
+        );
+    }
+}
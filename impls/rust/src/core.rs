@@ -1,29 +1,40 @@
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::hash::{DefaultHasher, Hash};
 use std::io::{self, BufWriter, Write};
+use std::rc::Rc;
 
 use crate::env::Env;
+use crate::lazy::{LazySeq, Step};
+use crate::list::List;
 use crate::printer;
-use crate::types::{MalError, MalVal};
+use crate::sorted::{self, Entry, Sorted};
+use crate::types::{
+    cached_regex, Arity, BigInt, BigRational, MalError, MalResult, MalVal, Seq, SEQABLE,
+};
+use fnv::FnvBuildHasher;
+use im_rc::{HashMap, HashSet, Vector};
 use itertools::Itertools;
+use num_bigint::Sign;
+use num_traits::{FromPrimitive, One, Signed, ToPrimitive, Zero};
+use regex::{Captures, Regex};
 
+// 数値の二項演算の引数を順に畳み込む. 演算の詳細はnum_opを参照
 #[macro_export]
 macro_rules! int_op {
-    ($name:expr, $func:expr) => {
+    // 引数がなければ単位元unitを返す
+    (unit = $unit:expr, $int:expr, $ratio:expr, $float:expr) => {
+        $crate::types::MalVal::BuiltinFn(|args| {
+            Ok(args
+                .into_iter()
+                .try_reduce(|acc, x| $crate::core::num_op(acc, x, $int, $ratio, $float))?
+                .unwrap_or($crate::types::MalVal::Number($unit)))
+        })
+    };
+    ($name:expr, $int:expr, $ratio:expr, $float:expr) => {
         $crate::types::MalVal::BuiltinFn(|args| {
             args.into_iter()
-                .try_reduce(|acc, x| match (acc, x) {
-                    ($crate::types::MalVal::Number(acc), $crate::types::MalVal::Number(x)) =>
-                    {
-                        #[allow(clippy::redundant_closure_call)]
-                        $func(acc, x)
-                    }
-                    (z, $crate::types::MalVal::Number(_)) | (_, z) => {
-                        Err($crate::types::MalError::InvalidType(
-                            $crate::printer::pr_str(&z, true),
-                            "number".to_string(),
-                            z.type_str(),
-                        ))
-                    }
-                })?
+                .try_reduce(|acc, x| $crate::core::num_op(acc, x, $int, $ratio, $float))?
                 .ok_or($crate::types::MalError::WrongArity(
                     $name.to_string(),
                     $crate::types::Arity::Variadic(1),
@@ -33,50 +44,83 @@ macro_rules! int_op {
     };
 }
 
-macro_rules! int_cmp {
+// 数値同士は値で比較する (NaNとの比較は常にfalse). 数値と数値以外の比較はエラー
+// どちらも数値でなければMalValの全順序で比較する
+macro_rules! mal_cmp {
     ($cmp:expr) => {
         $crate::types::MalVal::BuiltinFn(|args| {
-            args.into_iter()
+            args.iter()
                 .tuple_windows()
-                .try_fold(true, |acc, (a, b)| match (a, b) {
-                    ($crate::types::MalVal::Number(a), $crate::types::MalVal::Number(b)) =>
-                    {
-                        #[allow(clippy::redundant_closure_call)]
-                        Ok(acc && $cmp(a, b))
-                    }
-                    (z, MalVal::Number(_)) | (_, z) => Err($crate::types::MalError::InvalidType(
-                        $crate::printer::pr_str(&z, true),
+                .try_fold(true, |acc, (a, b)| match (a.to_f64(), b.to_f64()) {
+                    (Some(_), Some(_)) => Ok(acc && a.num_cmp(b).is_some_and($cmp)),
+                    (Some(_), None) => Err($crate::types::MalError::InvalidType(
+                        b.clone(),
+                        "number".to_string(),
+                    )),
+                    (None, Some(_)) => Err($crate::types::MalError::InvalidType(
+                        a.clone(),
                         "number".to_string(),
-                        z.type_str(),
                     )),
+                    (None, None) => Ok(acc && $cmp(a.value_cmp(b))),
                 })
                 .map($crate::types::MalVal::Bool)
         })
     };
 }
 
+thread_local! {
+    // catch*で捕捉して処理中のエラー. 入れ子のcatch*ごとに積む
+    static CAUGHT: RefCell<Vec<MalError>> = const { RefCell::new(Vec::new()) };
+    // 評価中のrestart-caseで有効なリスタート名
+    static RESTARTS: RefCell<Vec<MalVal>> = const { RefCell::new(Vec::new()) };
+}
+
+/// catch*の本体を評価する間、捕捉したエラーを (ex-trace) から参照できるようにする
+pub fn with_caught<T>(e: MalError, f: impl FnOnce() -> T) -> T {
+    CAUGHT.with_borrow_mut(|caught| caught.push(e));
+    let result = f();
+    CAUGHT.with_borrow_mut(|caught| caught.pop());
+    result
+}
+
+/// restart-caseの式を評価する間、そのリスタートをinvoke-restartから呼び出せるようにする
+pub fn with_restarts<T>(names: Vec<MalVal>, f: impl FnOnce() -> T) -> T {
+    let depth = RESTARTS.with_borrow(Vec::len);
+    RESTARTS.with_borrow_mut(|restarts| restarts.extend(names));
+    let result = f();
+    RESTARTS.with_borrow_mut(|restarts| restarts.truncate(depth));
+    result
+}
+
 // 一回しか呼ばれないのでinlineにしておく
 #[inline]
 pub fn env() -> Env {
     [
         (
             "+".to_string(),
-            int_op!("+", |a, b| Ok(MalVal::Number(a + b))),
+            int_op!(unit = 0, i64::checked_add, |a, b| Ok(a + b), |a, b| a + b),
         ),
         (
             "-".to_string(),
-            int_op!("-", |a, b| Ok(MalVal::Number(a - b))),
+            int_op!("-", i64::checked_sub, |a, b| Ok(a - b), |a, b| a - b),
         ),
         (
             "*".to_string(),
-            int_op!("*", |a, b| Ok(MalVal::Number(a * b))),
+            int_op!(unit = 1, i64::checked_mul, |a, b| Ok(a * b), |a, b| a * b),
         ),
         (
             "/".to_string(),
-            int_op!("/", |a: i64, b: i64| a
-                .checked_div(b)
-                .map(MalVal::Number)
-                .ok_or(MalError::DividedByZero)),
+            // 割り切れない場合は分数になる
+            int_op!(
+                "/",
+                |a: i64, b: i64| a.checked_rem(b).filter(|r| *r == 0).and(a.checked_div(b)),
+                |a, b: BigRational| if b.is_zero() {
+                    Err(MalError::DividedByZero)
+                } else {
+                    Ok(a / b)
+                },
+                |a, b| a / b
+            ),
         ),
         (
             "list".to_string(),
@@ -93,89 +137,3284 @@ pub fn env() -> Env {
         ),
         (
             "empty?".to_string(),
-            MalVal::BuiltinFn(|args| {
-                Ok(MalVal::Bool(
-                    matches!(
-                        args.first(),
-                        Some(MalVal::List(v, _) | MalVal::Vector(v, _)) if v.is_empty()
-                    ) || matches!(
-                        args.first(),
-                        Some(MalVal::HashMap(map, _)) if map.is_empty()
-                    ) || matches!(
-                        args.first(),
-                        Some(MalVal::HashSet(set, _)) if set.is_empty()
-                    ),
-                ))
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [coll] => Ok(MalVal::Bool(seq_of(coll)?.next().transpose()?.is_none())),
+                _ => Err(MalError::WrongArity(
+                    "empty?".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
             }),
         ),
         (
             "count".to_string(),
-            MalVal::BuiltinFn(|args| match args.first() {
-                None | Some(MalVal::Nil) => Ok(MalVal::Number(0)),
-                Some(MalVal::List(v, _) | MalVal::Vector(v, _)) => {
-                    Ok(MalVal::Number(v.len() as i64))
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [coll] => read_transient(coll, |coll| Ok(MalVal::Number(count_of(coll)? as i64))),
+                _ => Err(MalError::WrongArity(
+                    "count".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "conj".to_string(),
+            MalVal::BuiltinFn(|mut args| {
+                if args.is_empty() {
+                    return Err(MalError::WrongArity(
+                        "conj".to_string(),
+                        Arity::Variadic(1),
+                        0,
+                    ));
+                }
+                let xs = args.split_off(1);
+                conj(args.pop().unwrap(), xs)
+            }),
+        ),
+        (
+            "cons".to_string(),
+            MalVal::BuiltinFn(|mut args| {
+                if args.len() != 2 {
+                    return Err(MalError::WrongArity(
+                        "cons".to_string(),
+                        Arity::Fixed(2),
+                        args.len(),
+                    ));
+                }
+                let coll = args.pop().unwrap();
+                let x = args.pop().unwrap();
+                match coll {
+                    MalVal::Nil => Ok(MalVal::List(List::new().cons(x), Rc::new(MalVal::Nil))),
+                    // リストは末尾を共有するのでコピーしない
+                    MalVal::List(l, _) => Ok(MalVal::List(l.cons(x), Rc::new(MalVal::Nil))),
+                    // 遅延シーケンスは計算せずに先頭に繋げる
+                    MalVal::LazySeq(_) => Ok(MalVal::LazySeq(Rc::new(LazySeq::cons(x, coll)))),
+                    _ => Ok(MalVal::List(
+                        std::iter::once(Ok(x)).chain(seq_of(&coll)?).try_collect()?,
+                        Rc::new(MalVal::Nil),
+                    )),
                 }
-                Some(MalVal::HashMap(map, _)) => Ok(MalVal::Number(map.len() as i64)),
-                Some(MalVal::HashSet(set, _)) => Ok(MalVal::Number(set.len() as i64)),
-                Some(z) => Err(MalError::InvalidType(
-                    printer::pr_str(z, true),
-                    "nil, list, vector, hashmap or hashset".to_string(),
-                    z.type_str(),
+            }),
+        ),
+        (
+            "seq".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [coll] => seq(coll),
+                _ => Err(MalError::WrongArity(
+                    "seq".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
                 )),
             }),
         ),
         (
-            "=".to_string(),
-            MalVal::BuiltinFn(|args| Ok(MalVal::Bool(args.into_iter().all_equal()))),
+            "first".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [coll] => Ok(seq_of(coll)?.next().transpose()?.unwrap_or(MalVal::Nil)),
+                _ => Err(MalError::WrongArity(
+                    "first".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
         ),
-        ("<".to_string(), int_cmp!(|a, b| a < b)),
-        ("<=".to_string(), int_cmp!(|a, b| a <= b)),
-        (">".to_string(), int_cmp!(|a, b| a > b)),
-        (">=".to_string(), int_cmp!(|a, b| a >= b)),
         (
-            "pr-str".to_string(),
-            // NOTE: joinはDisplay実装を用いてString化を行う
-            MalVal::BuiltinFn(|args| Ok(MalVal::string(args.into_iter().join(" ")))),
+            "rest".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [coll] => rest(coll),
+                _ => Err(MalError::WrongArity(
+                    "rest".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
         ),
         (
-            "str".to_string(),
+            "next".to_string(),
+            // restと同じだが、空の場合はnil
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [coll] => seq(&rest(coll)?),
+                _ => Err(MalError::WrongArity(
+                    "next".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "nth".to_string(),
+            // (nth coll index not-found?): not-foundがなければ範囲外はエラー
             MalVal::BuiltinFn(|args| {
-                Ok(MalVal::string(
-                    args.into_iter()
-                        .map(|x| printer::pr_str(&x, false))
-                        .collect::<String>(),
-                ))
+                let (coll, index, not_found) = match args.as_slice() {
+                    [coll, index] => (coll, index, None),
+                    [coll, index, not_found] => (coll, index, Some(not_found)),
+                    _ => {
+                        return Err(MalError::WrongArity(
+                            "nth".to_string(),
+                            Arity::JustOrOneLess(3),
+                            args.len(),
+                        ))
+                    }
+                };
+                let MalVal::Number(i) = index else {
+                    return Err(MalError::InvalidType(index.clone(), "integer".to_string()));
+                };
+                let found = match coll {
+                    MalVal::Vector(v, _) => {
+                        usize::try_from(*i).ok().and_then(|i| v.get(i)).cloned()
+                    }
+                    _ => match usize::try_from(*i) {
+                        Ok(i) => seq_of(coll)?.nth(i).transpose()?,
+                        Err(_) => None,
+                    },
+                };
+                match (found, not_found) {
+                    (Some(x), _) => Ok(x),
+                    (None, Some(not_found)) => Ok(not_found.clone()),
+                    (None, None) => Err(MalError::IndexOutOfBounds(*i, count_of(coll)?)),
+                }
             }),
         ),
         (
-            "prn".to_string(),
+            "last".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [MalVal::Vector(v, _)] => Ok(v.back().cloned().unwrap_or(MalVal::Nil)),
+                [coll] => seq_of(coll)?.try_fold(MalVal::Nil, |_, x| x),
+                _ => Err(MalError::WrongArity(
+                    "last".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "butlast".to_string(),
+            // 最後の要素を除いたリスト. 空になる場合はnil
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [coll] => {
+                    let mut xs = seq_items(coll)?;
+                    xs.pop();
+                    Ok(if xs.is_empty() {
+                        MalVal::Nil
+                    } else {
+                        MalVal::list(xs)
+                    })
+                }
+                _ => Err(MalError::WrongArity(
+                    "butlast".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "take".to_string(),
+            // (take n) はtransducer
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [MalVal::Number(n)] => Ok(take_xf(*n)),
+                _ => {
+                    let (n, coll) = count_args("take", &args)?;
+                    Ok(take(n, coll.clone()))
+                }
+            }),
+        ),
+        (
+            "drop".to_string(),
             MalVal::BuiltinFn(|args| {
-                fast_print(args.into_iter().map(|x| printer::pr_str(&x, true)));
-                Ok(MalVal::Nil)
+                let (n, coll) = count_args("drop", &args)?;
+                match coll {
+                    // リストは末尾を共有する
+                    MalVal::List(l, _) => Ok(MalVal::List(
+                        (0..n.min(l.len())).fold(l.clone(), |l, _| l.rest()),
+                        Rc::new(MalVal::Nil),
+                    )),
+                    _ => Ok(drop(n, coll.clone())),
+                }
             }),
         ),
         (
-            "println".to_string(),
+            "into".to_string(),
+            // fromの要素をtoにconjする. (into to xform from) はxformを通した要素をconjする
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [to, from] => conj(to.clone(), seq_items(from)?),
+                [to, xform, from] => {
+                    let rf = MalVal::BuiltinFn(|mut args| match args.len() {
+                        // 完了時はそのまま返す
+                        1 => Ok(args.pop().unwrap()),
+                        2 => {
+                            let x = args.pop().unwrap();
+                            conj(args.pop().unwrap(), vec![x])
+                        }
+                        n => Err(MalError::WrongArity(
+                            "into".to_string(),
+                            Arity::JustOrOneLess(2),
+                            n,
+                        )),
+                    });
+                    transduce(xform, rf, to.clone(), from)
+                }
+                _ => Err(MalError::WrongArity(
+                    "into".to_string(),
+                    Arity::JustOrOneLess(3),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "assoc".to_string(),
+            MalVal::BuiltinFn(|mut args| {
+                if args.len() < 3 || args.len() % 2 == 0 {
+                    return Err(MalError::WrongArity(
+                        "assoc".to_string(),
+                        Arity::Variadic(3),
+                        args.len(),
+                    ));
+                }
+                let kvs = args.split_off(1);
+                assoc(args.pop().unwrap(), kvs)
+            }),
+        ),
+        (
+            "dissoc".to_string(),
+            MalVal::BuiltinFn(|mut args| {
+                if args.is_empty() {
+                    return Err(MalError::WrongArity(
+                        "dissoc".to_string(),
+                        Arity::Variadic(1),
+                        0,
+                    ));
+                }
+                let ks = args.split_off(1);
+                dissoc(args.pop().unwrap(), &ks)
+            }),
+        ),
+        (
+            "transient".to_string(),
             MalVal::BuiltinFn(|args| {
-                fast_print(args.into_iter().map(|x| printer::pr_str(&x, false)));
-                Ok(MalVal::Nil)
+                // 永続コレクションの複製はO(1)で、複製したRcはtransientだけが所有する
+                let coll = match args.as_slice() {
+                    [MalVal::Vector(v, _)] => MalVal::vec((**v).clone()),
+                    [MalVal::HashMap(m, _)] => MalVal::hashmap((**m).clone()),
+                    [MalVal::HashSet(s, _)] => MalVal::hashset((**s).clone()),
+                    [z] => {
+                        return Err(MalError::InvalidType(
+                            z.clone(),
+                            "vector, hash-map or hash-set".to_string(),
+                        ))
+                    }
+                    _ => {
+                        return Err(MalError::WrongArity(
+                            "transient".to_string(),
+                            Arity::Fixed(1),
+                            args.len(),
+                        ))
+                    }
+                };
+                Ok(MalVal::Transient(Rc::new(RefCell::new(Some(coll)))))
             }),
         ),
-    ]
-    .into()
-}
-
-fn fast_print<I>(mut s: I)
-where
-    I: Iterator<Item = String>,
-{
-    let mut out = BufWriter::new(io::stdout().lock());
-    if let Some(str) = s.next() {
-        out.write_all(str.as_bytes()).unwrap();
-        s.for_each(|str| {
-            out.write_all(&[b' ']).unwrap();
-            out.write_all(str.as_bytes()).unwrap();
-        });
-    }
-    out.write_all(&[b'\n']).unwrap();
+        (
+            "persistent!".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [MalVal::Transient(t)] => t.borrow_mut().take().ok_or(MalError::PersistedTransient),
+                [z] => Err(MalError::InvalidType(z.clone(), "transient".to_string())),
+                _ => Err(MalError::WrongArity(
+                    "persistent!".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "conj!".to_string(),
+            MalVal::BuiltinFn(|args| {
+                edit_transient("conj!", Arity::Variadic(1), args, |coll, xs| match coll {
+                    MalVal::Vector(v, _) => {
+                        owned(v)?.extend(xs);
+                        Ok(())
+                    }
+                    MalVal::HashMap(m, _) => {
+                        let m = owned(m)?;
+                        for x in xs {
+                            m.extend(map_entries(x)?);
+                        }
+                        Ok(())
+                    }
+                    MalVal::HashSet(s, _) => {
+                        owned(s)?.extend(xs);
+                        Ok(())
+                    }
+                    _ => unreachable!("transient holds only vectors, hash-maps and hash-sets"),
+                })
+            }),
+        ),
+        (
+            "assoc!".to_string(),
+            MalVal::BuiltinFn(|args| {
+                if args.len() < 3 || args.len() % 2 == 0 {
+                    return Err(MalError::WrongArity(
+                        "assoc!".to_string(),
+                        Arity::Variadic(3),
+                        args.len(),
+                    ));
+                }
+                edit_transient("assoc!", Arity::Variadic(3), args, |coll, kvs| match coll {
+                    MalVal::HashMap(m, _) => {
+                        owned(m)?.extend(kvs.into_iter().tuples());
+                        Ok(())
+                    }
+                    MalVal::Vector(v, _) => {
+                        let v = owned(v)?;
+                        kvs.into_iter()
+                            .tuples()
+                            .try_for_each(|(k, x)| assoc_vec(v, k, x))
+                    }
+                    z => Err(MalError::InvalidType(
+                        z.clone(),
+                        "transient hash-map or vector".to_string(),
+                    )),
+                })
+            }),
+        ),
+        (
+            "dissoc!".to_string(),
+            MalVal::BuiltinFn(|args| {
+                edit_transient("dissoc!", Arity::Variadic(1), args, |coll, ks| match coll {
+                    MalVal::HashMap(m, _) => {
+                        let m = owned(m)?;
+                        ks.iter().for_each(|k| {
+                            m.remove(k);
+                        });
+                        Ok(())
+                    }
+                    z => Err(MalError::InvalidType(
+                        z.clone(),
+                        "transient hash-map".to_string(),
+                    )),
+                })
+            }),
+        ),
+        (
+            "disj!".to_string(),
+            MalVal::BuiltinFn(|args| {
+                edit_transient("disj!", Arity::Variadic(1), args, |coll, xs| match coll {
+                    MalVal::HashSet(s, _) => {
+                        let s = owned(s)?;
+                        xs.iter().for_each(|x| {
+                            s.remove(x);
+                        });
+                        Ok(())
+                    }
+                    z => Err(MalError::InvalidType(
+                        z.clone(),
+                        "transient hash-set".to_string(),
+                    )),
+                })
+            }),
+        ),
+        (
+            "get".to_string(),
+            MalVal::BuiltinFn(|args| {
+                let (coll, key, default) = match args.as_slice() {
+                    [coll, key] => (coll, key, MalVal::Nil),
+                    [coll, key, default] => (coll, key, default.clone()),
+                    _ => {
+                        return Err(MalError::WrongArity(
+                            "get".to_string(),
+                            Arity::JustOrOneLess(3),
+                            args.len(),
+                        ))
+                    }
+                };
+                Ok(read_transient(coll, |coll| coll.get(key))?.unwrap_or(default))
+            }),
+        ),
+        (
+            "get-in".to_string(),
+            // (get-in coll path not-found?): 途中でキーが見つからなければnot-found
+            MalVal::BuiltinFn(|args| {
+                let (coll, path, not_found) = match args.as_slice() {
+                    [coll, path] => (coll, path, MalVal::Nil),
+                    [coll, path, not_found] => (coll, path, not_found.clone()),
+                    _ => {
+                        return Err(MalError::WrongArity(
+                            "get-in".to_string(),
+                            Arity::JustOrOneLess(3),
+                            args.len(),
+                        ))
+                    }
+                };
+                let mut coll = coll.clone();
+                for k in seq_of(path)? {
+                    match coll.get(&k?)? {
+                        Some(x) => coll = x,
+                        None => return Ok(not_found),
+                    }
+                }
+                Ok(coll)
+            }),
+        ),
+        (
+            "assoc-in".to_string(),
+            MalVal::BuiltinFn(|args| match <[MalVal; 3]>::try_from(args) {
+                Ok([coll, path, x]) => update_in(coll, &seq_items(&path)?, &mut |_| Ok(x.clone())),
+                Err(args) => Err(MalError::WrongArity(
+                    "assoc-in".to_string(),
+                    Arity::Fixed(3),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "update".to_string(),
+            // (update coll k f args...) は (f (get coll k) args...) で置き換える
+            MalVal::BuiltinFn(|mut args| {
+                if args.len() < 3 {
+                    return Err(MalError::WrongArity(
+                        "update".to_string(),
+                        Arity::Variadic(3),
+                        args.len(),
+                    ));
+                }
+                let rest = args.split_off(3);
+                let [coll, k, f] = <[MalVal; 3]>::try_from(args).unwrap();
+                update_in(coll, &[k], &mut |x| {
+                    f.apply(std::iter::once(x).chain(rest.iter().cloned()).collect())
+                })
+            }),
+        ),
+        (
+            "update-in".to_string(),
+            MalVal::BuiltinFn(|mut args| {
+                if args.len() < 3 {
+                    return Err(MalError::WrongArity(
+                        "update-in".to_string(),
+                        Arity::Variadic(3),
+                        args.len(),
+                    ));
+                }
+                let rest = args.split_off(3);
+                let [coll, path, f] = <[MalVal; 3]>::try_from(args).unwrap();
+                update_in(coll, &seq_items(&path)?, &mut |x| {
+                    f.apply(std::iter::once(x).chain(rest.iter().cloned()).collect())
+                })
+            }),
+        ),
+        (
+            "dissoc-in".to_string(),
+            MalVal::BuiltinFn(|args| match <[MalVal; 2]>::try_from(args) {
+                Ok([coll, path]) => dissoc_in(coll, &seq_items(&path)?),
+                Err(args) => Err(MalError::WrongArity(
+                    "dissoc-in".to_string(),
+                    Arity::Fixed(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "merge".to_string(),
+            // 後のマップの値が優先される. nilは無視し、全てnilならnil
+            MalVal::BuiltinFn(|args| {
+                let mut maps = args.into_iter().filter(|m| !matches!(m, MalVal::Nil));
+                match maps.next() {
+                    Some(m) => conj(m, maps.collect()),
+                    None => Ok(MalVal::Nil),
+                }
+            }),
+        ),
+        (
+            "merge-with".to_string(),
+            // 同じキーがあれば (f 前の値 後の値) にする
+            MalVal::BuiltinFn(|mut args| {
+                if args.is_empty() {
+                    return Err(MalError::WrongArity(
+                        "merge-with".to_string(),
+                        Arity::Variadic(1),
+                        0,
+                    ));
+                }
+                let maps = args.split_off(1);
+                let f = args.pop().unwrap();
+                let mut maps = maps.into_iter().filter(|m| !matches!(m, MalVal::Nil));
+                let Some(mut acc) = maps.next() else {
+                    return Ok(MalVal::Nil);
+                };
+                for m in maps {
+                    for (k, v) in map_entries(m)? {
+                        let v = match acc.map_get(&k) {
+                            Some(old) => f.apply(vec![old.clone(), v])?,
+                            None => v,
+                        };
+                        acc = assoc(acc, vec![k, v])?;
+                    }
+                }
+                Ok(acc)
+            }),
+        ),
+        (
+            "select-keys".to_string(),
+            // collにあるキーだけを残したマップ
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [coll, ks] => {
+                    let mut m = HashMap::default();
+                    for k in seq_of(ks)? {
+                        let k = k?;
+                        if let Some(x) = coll.get(&k)? {
+                            m.insert(k, x);
+                        }
+                    }
+                    Ok(MalVal::hashmap(m))
+                }
+                _ => Err(MalError::WrongArity(
+                    "select-keys".to_string(),
+                    Arity::Fixed(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "rename-keys".to_string(),
+            // kmapの {古いキー 新しいキー} に従ってキーを付け替える
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [m, kmap] => {
+                    let renames = map_entries(kmap.clone())?;
+                    let olds = renames.iter().map(|(old, _)| old.clone()).collect_vec();
+                    let mut acc = dissoc(m.clone(), &olds)?;
+                    for (old, new) in renames {
+                        if let Some(x) = m.map_get(&old) {
+                            acc = assoc(acc, vec![new, x.clone()])?;
+                        }
+                    }
+                    Ok(acc)
+                }
+                _ => Err(MalError::WrongArity(
+                    "rename-keys".to_string(),
+                    Arity::Fixed(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "find".to_string(),
+            // キーがあれば [k v]. ベクタはインデックスをキーとする
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [coll @ (MalVal::Nil
+                | MalVal::HashMap(_, _)
+                | MalVal::SortedMap(_, _)
+                | MalVal::Vector(_, _)), k] => Ok(coll
+                    .get(k)?
+                    .map_or(MalVal::Nil, |v| map_entry(k.clone(), v))),
+                [coll, _] => Err(MalError::InvalidType(
+                    coll.clone(),
+                    "nil, map or vector".to_string(),
+                )),
+                _ => Err(MalError::WrongArity(
+                    "find".to_string(),
+                    Arity::Fixed(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "key".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [MalVal::Vector(v, _)] if v.len() == 2 => Ok(v[0].clone()),
+                [x] => Err(MalError::InvalidType(x.clone(), "map entry".to_string())),
+                _ => Err(MalError::WrongArity(
+                    "key".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "val".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [MalVal::Vector(v, _)] if v.len() == 2 => Ok(v[1].clone()),
+                [x] => Err(MalError::InvalidType(x.clone(), "map entry".to_string())),
+                _ => Err(MalError::WrongArity(
+                    "val".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "empty".to_string(),
+            // 同じ種類の空のコレクション. メタデータと比較関数は引き継ぐ
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [coll] => Ok(match coll {
+                    MalVal::List(_, meta) => MalVal::List(List::new(), meta.clone()),
+                    MalVal::Vector(_, meta) => MalVal::Vector(Rc::default(), meta.clone()),
+                    MalVal::LazySeq(_) => MalVal::list(vec![]),
+                    MalVal::HashMap(_, meta) => MalVal::HashMap(Rc::default(), meta.clone()),
+                    MalVal::HashSet(_, meta) => MalVal::HashSet(Rc::default(), meta.clone()),
+                    MalVal::SortedMap(m, meta) => {
+                        MalVal::SortedMap(Rc::new(m.empty()), meta.clone())
+                    }
+                    MalVal::SortedSet(set, meta) => {
+                        MalVal::SortedSet(Rc::new(set.empty()), meta.clone())
+                    }
+                    _ => MalVal::Nil,
+                }),
+                _ => Err(MalError::WrongArity(
+                    "empty".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "contains?".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [coll, k] => read_transient(coll, |coll| {
+                    Ok(MalVal::Bool(match (coll, k) {
+                        (MalVal::HashMap(m, _), k) => m.contains_key(k),
+                        (MalVal::HashSet(set, _), k) => set.contains(k),
+                        (MalVal::SortedMap(m, _), k) => m.get(k)?.is_some(),
+                        (MalVal::SortedSet(set, _), k) => set.get(k)?.is_some(),
+                        (MalVal::Vector(v, _), MalVal::Number(i)) => {
+                            (0..v.len() as i64).contains(i)
+                        }
+                        _ => false,
+                    }))
+                }),
+                _ => Err(MalError::WrongArity(
+                    "contains?".to_string(),
+                    Arity::Fixed(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "hash-map".to_string(),
+            MalVal::BuiltinFn(|args| Ok(MalVal::hashmap(kv_pairs("hash-map", args)?.collect()))),
+        ),
+        (
+            "hash-set".to_string(),
+            MalVal::BuiltinFn(|args| Ok(MalVal::hashset(args.into_iter().collect()))),
+        ),
+        (
+            "set".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [MalVal::Nil] => Ok(MalVal::hashset(HashSet::default())),
+                [MalVal::HashSet(set, _)] => Ok(MalVal::hashset((**set).clone())),
+                // マップは[k v]のベクタの集合にする
+                [coll] => Ok(MalVal::hashset(seq_of(coll)?.try_collect()?)),
+                _ => Err(MalError::WrongArity(
+                    "set".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "set?".to_string(),
+            MalVal::BuiltinFn(|args| {
+                Ok(MalVal::Bool(matches!(
+                    args.first(),
+                    Some(MalVal::HashSet(_, _) | MalVal::SortedSet(_, _))
+                )))
+            }),
+        ),
+        (
+            "disj".to_string(),
+            MalVal::BuiltinFn(|mut args| {
+                if args.is_empty() {
+                    return Err(MalError::WrongArity(
+                        "disj".to_string(),
+                        Arity::Variadic(1),
+                        0,
+                    ));
+                }
+                let xs = args.split_off(1);
+                match args.pop().unwrap() {
+                    MalVal::Nil => Ok(MalVal::Nil),
+                    MalVal::HashSet(set, meta) => {
+                        let mut set = Rc::unwrap_or_clone(set);
+                        xs.iter().for_each(|x| {
+                            set.remove(x);
+                        });
+                        Ok(MalVal::HashSet(Rc::new(set), meta))
+                    }
+                    MalVal::SortedSet(set, meta) => {
+                        let mut set = Rc::unwrap_or_clone(set);
+                        xs.iter().try_for_each(|x| set.remove(x))?;
+                        Ok(MalVal::SortedSet(Rc::new(set), meta))
+                    }
+                    z => Err(MalError::InvalidType(z, "nil or set".to_string())),
+                }
+            }),
+        ),
+        (
+            "union".to_string(),
+            MalVal::BuiltinFn(|args| Ok(MalVal::hashset(HashSet::unions(into_sets(args)?)))),
+        ),
+        (
+            "intersection".to_string(),
+            MalVal::BuiltinFn(|args| {
+                into_sets(args)?
+                    .into_iter()
+                    .reduce(HashSet::intersection)
+                    .map(MalVal::hashset)
+                    .ok_or(MalError::WrongArity(
+                        "intersection".to_string(),
+                        Arity::Variadic(1),
+                        0,
+                    ))
+            }),
+        ),
+        (
+            "difference".to_string(),
+            MalVal::BuiltinFn(|args| {
+                into_sets(args)?
+                    .into_iter()
+                    .reduce(HashSet::relative_complement)
+                    .map(MalVal::hashset)
+                    .ok_or(MalError::WrongArity(
+                        "difference".to_string(),
+                        Arity::Variadic(1),
+                        0,
+                    ))
+            }),
+        ),
+        (
+            "subset?".to_string(),
+            MalVal::BuiltinFn(|args| match into_sets(args)?.as_slice() {
+                [a, b] => Ok(MalVal::Bool(a.is_subset(b))),
+                sets => Err(MalError::WrongArity(
+                    "subset?".to_string(),
+                    Arity::Fixed(2),
+                    sets.len(),
+                )),
+            }),
+        ),
+        (
+            "superset?".to_string(),
+            MalVal::BuiltinFn(|args| match into_sets(args)?.as_slice() {
+                [a, b] => Ok(MalVal::Bool(b.is_subset(a))),
+                sets => Err(MalError::WrongArity(
+                    "superset?".to_string(),
+                    Arity::Fixed(2),
+                    sets.len(),
+                )),
+            }),
+        ),
+        (
+            "sorted-map".to_string(),
+            MalVal::BuiltinFn(|args| sorted_map("sorted-map", None, args)),
+        ),
+        (
+            "sorted-map-by".to_string(),
+            MalVal::BuiltinFn(|mut args| {
+                if args.is_empty() {
+                    return Err(MalError::WrongArity(
+                        "sorted-map-by".to_string(),
+                        Arity::Variadic(1),
+                        0,
+                    ));
+                }
+                let kvs = args.split_off(1);
+                sorted_map("sorted-map-by", args.pop(), kvs)
+            }),
+        ),
+        (
+            "sorted-set".to_string(),
+            MalVal::BuiltinFn(|args| {
+                let mut set = Sorted::new(None);
+                args.into_iter().try_for_each(|x| set.insert(x))?;
+                Ok(MalVal::sorted_set(set))
+            }),
+        ),
+        (
+            "sorted?".to_string(),
+            MalVal::BuiltinFn(|args| {
+                Ok(MalVal::Bool(matches!(
+                    args.first(),
+                    Some(MalVal::SortedMap(_, _) | MalVal::SortedSet(_, _))
+                )))
+            }),
+        ),
+        (
+            "subseq".to_string(),
+            MalVal::BuiltinFn(|args| Ok(MalVal::list(subseq("subseq", &args)?))),
+        ),
+        (
+            "rsubseq".to_string(),
+            MalVal::BuiltinFn(|args| {
+                let mut xs = subseq("rsubseq", &args)?;
+                xs.reverse();
+                Ok(MalVal::list(xs))
+            }),
+        ),
+        (
+            "apply".to_string(),
+            MalVal::BuiltinFn(|mut args| {
+                if args.len() < 2 {
+                    return Err(MalError::WrongArity(
+                        "apply".to_string(),
+                        Arity::Variadic(2),
+                        args.len(),
+                    ));
+                }
+                // 最後の引数のコレクションを展開して残りの引数に続ける
+                let last = seq_items(&args.pop().unwrap())?;
+                let f = args.remove(0);
+                args.extend(last);
+                f.apply(args)
+            }),
+        ),
+        (
+            "map".to_string(),
+            // (map f) はtransducer
+            MalVal::BuiltinFn(|mut args| match args.len() {
+                0 => Err(MalError::WrongArity(
+                    "map".to_string(),
+                    Arity::Variadic(1),
+                    0,
+                )),
+                1 => Ok(map_xf(args.pop().unwrap())),
+                _ => {
+                    let colls = args.split_off(1);
+                    Ok(map(args.pop().unwrap(), colls))
+                }
+            }),
+        ),
+        (
+            "filter".to_string(),
+            // (filter pred) はtransducer
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [pred] => Ok(filter_xf(pred.clone())),
+                [pred, coll] => Ok(filter(pred.clone(), coll.clone(), true)),
+                _ => Err(MalError::WrongArity(
+                    "filter".to_string(),
+                    Arity::JustOrOneLess(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "range".to_string(),
+            // (range), (range end), (range start end), (range start end step)
+            // endがなければ無限に続く
+            MalVal::BuiltinFn(|args| {
+                if let Some(x) = args.iter().find(|x| x.to_f64().is_none()) {
+                    return Err(MalError::InvalidType(x.clone(), "number".to_string()));
+                }
+                let (zero, one) = (MalVal::Number(0), MalVal::Number(1));
+                match args.as_slice() {
+                    [] => Ok(range(zero, None, one)),
+                    [end] => Ok(range(zero, Some(end.clone()), one)),
+                    [start, end] => Ok(range(start.clone(), Some(end.clone()), one)),
+                    [start, end, step] => Ok(range(start.clone(), Some(end.clone()), step.clone())),
+                    // 引数が多すぎる場合のみ
+                    _ => Err(MalError::WrongArity(
+                        "range".to_string(),
+                        Arity::Fixed(3),
+                        args.len(),
+                    )),
+                }
+            }),
+        ),
+        (
+            "iterate".to_string(),
+            // x, (f x), (f (f x)), ...
+            MalVal::BuiltinFn(|args| match <[MalVal; 2]>::try_from(args) {
+                Ok([f, x]) => Ok(iterate(f, x)),
+                Err(args) => Err(MalError::WrongArity(
+                    "iterate".to_string(),
+                    Arity::Fixed(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "repeat".to_string(),
+            // (repeat x) は無限に、(repeat n x) はn回xを繰り返す
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [x] => Ok(repeat(x.clone(), None)),
+                [MalVal::Number(n), x] => Ok(repeat(x.clone(), Some((*n).max(0) as usize))),
+                [n, _] => Err(MalError::InvalidType(n.clone(), "integer".to_string())),
+                _ => Err(MalError::WrongArity(
+                    "repeat".to_string(),
+                    Arity::JustOrOneLess(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "cycle".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [coll] => Ok(cycle(coll.clone(), MalVal::Nil)),
+                _ => Err(MalError::WrongArity(
+                    "cycle".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "take-while".to_string(),
+            MalVal::BuiltinFn(|args| match <[MalVal; 2]>::try_from(args) {
+                Ok([pred, coll]) => Ok(take_while(pred, coll)),
+                Err(args) => Err(MalError::WrongArity(
+                    "take-while".to_string(),
+                    Arity::Fixed(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "drop-while".to_string(),
+            MalVal::BuiltinFn(|args| match <[MalVal; 2]>::try_from(args) {
+                Ok([pred, coll]) => Ok(lazy(move || {
+                    let mut coll = coll;
+                    loop {
+                        match coll.uncons()? {
+                            Some((x, rest)) if truthy(&pred.apply(vec![x.clone()])?) => coll = rest,
+                            step => return Ok(step),
+                        }
+                    }
+                })),
+                Err(args) => Err(MalError::WrongArity(
+                    "drop-while".to_string(),
+                    Arity::Fixed(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "remove".to_string(),
+            MalVal::BuiltinFn(|args| match <[MalVal; 2]>::try_from(args) {
+                Ok([pred, coll]) => Ok(filter(pred, coll, false)),
+                Err(args) => Err(MalError::WrongArity(
+                    "remove".to_string(),
+                    Arity::Fixed(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "keep".to_string(),
+            // (f x) がnilでないものを残す
+            MalVal::BuiltinFn(|args| match <[MalVal; 2]>::try_from(args) {
+                Ok([f, coll]) => Ok(keep(f, coll)),
+                Err(args) => Err(MalError::WrongArity(
+                    "keep".to_string(),
+                    Arity::Fixed(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "reduce".to_string(),
+            // (reduce f coll) または (reduce f init coll)
+            // initがなければ最初の要素から始め、空なら (f) を返す
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [f, coll] => {
+                    let mut xs = seq_of(coll)?;
+                    match xs.next().transpose()? {
+                        Some(init) => reduce(f, init, xs.map(|x| x.map(|x| vec![x]))),
+                        None => f.apply(vec![]),
+                    }
+                }
+                [f, init, coll] => {
+                    reduce(f, init.clone(), seq_of(coll)?.map(|x| x.map(|x| vec![x])))
+                }
+                _ => Err(MalError::WrongArity(
+                    "reduce".to_string(),
+                    Arity::JustOrOneLess(3),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "reduced".to_string(),
+            MalVal::BuiltinFn(|args| match <[MalVal; 1]>::try_from(args) {
+                Ok([x]) => Ok(MalVal::Reduced(Rc::new(x))),
+                Err(args) => Err(MalError::WrongArity(
+                    "reduced".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "reduced?".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [x] => Ok(MalVal::Bool(matches!(x, MalVal::Reduced(_)))),
+                _ => Err(MalError::WrongArity(
+                    "reduced?".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "reduce-kv".to_string(),
+            // (f acc k v) を順に適用する. ベクタはインデックスをキーとする
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [f, init, MalVal::Nil] => reduce(f, init.clone(), std::iter::empty()),
+                [f, init, MalVal::Vector(v, _)] => reduce(
+                    f,
+                    init.clone(),
+                    v.iter()
+                        .enumerate()
+                        .map(|(i, x)| Ok(vec![MalVal::Number(i as i64), x.clone()])),
+                ),
+                [f, init, coll] => {
+                    let m = coll.map_iter().ok_or(MalError::InvalidType(
+                        coll.clone(),
+                        "nil, map or vector".to_string(),
+                    ))?;
+                    reduce(
+                        f,
+                        init.clone(),
+                        m.map(|(k, v)| Ok(vec![k.clone(), v.clone()])),
+                    )
+                }
+                _ => Err(MalError::WrongArity(
+                    "reduce-kv".to_string(),
+                    Arity::Fixed(3),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "some".to_string(),
+            // (pred x) が真になった最初の結果. なければnil
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [pred, coll] => {
+                    for x in seq_of(coll)? {
+                        let found = pred.apply(vec![x?])?;
+                        if truthy(&found) {
+                            return Ok(found);
+                        }
+                    }
+                    Ok(MalVal::Nil)
+                }
+                _ => Err(MalError::WrongArity(
+                    "some".to_string(),
+                    Arity::Fixed(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "every?".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [pred, coll] => {
+                    for x in seq_of(coll)? {
+                        if !truthy(&pred.apply(vec![x?])?) {
+                            return Ok(MalVal::Bool(false));
+                        }
+                    }
+                    Ok(MalVal::Bool(true))
+                }
+                _ => Err(MalError::WrongArity(
+                    "every?".to_string(),
+                    Arity::Fixed(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "mapcat".to_string(),
+            // mapの結果を繋げる
+            MalVal::BuiltinFn(|mut args| {
+                if args.len() < 2 {
+                    return Err(MalError::WrongArity(
+                        "mapcat".to_string(),
+                        Arity::Variadic(2),
+                        args.len(),
+                    ));
+                }
+                let colls = args.split_off(1);
+                Ok(concat(map(args.pop().unwrap(), colls), MalVal::Nil))
+            }),
+        ),
+        (
+            "interleave".to_string(),
+            // 各コレクションから順に一つずつ取り出す. 最も短いものに合わせる
+            MalVal::BuiltinFn(|args| {
+                if args.is_empty() {
+                    return Ok(MalVal::list(vec![]));
+                }
+                let f = MalVal::BuiltinFn(|xs| Ok(MalVal::list(xs)));
+                Ok(concat(map(f, args), MalVal::Nil))
+            }),
+        ),
+        (
+            "partition".to_string(),
+            // (partition n coll), (partition n step coll), (partition n step pad coll)
+            // n個に満たない最後の部分は、padがあれば補い、なければ捨てる
+            MalVal::BuiltinFn(|args| {
+                let size = |x: &MalVal| match x {
+                    MalVal::Number(n) if *n > 0 => Ok(*n as usize),
+                    _ => Err(MalError::InvalidType(
+                        x.clone(),
+                        "positive integer".to_string(),
+                    )),
+                };
+                match args.as_slice() {
+                    [n, coll] => Ok(partition(size(n)?, size(n)?, None, coll.clone())),
+                    [n, step, coll] => Ok(partition(size(n)?, size(step)?, None, coll.clone())),
+                    [n, step, pad, coll] => Ok(partition(
+                        size(n)?,
+                        size(step)?,
+                        Some(pad.clone()),
+                        coll.clone(),
+                    )),
+                    _ => Err(MalError::WrongArity(
+                        "partition".to_string(),
+                        if args.len() < 2 {
+                            Arity::Variadic(2)
+                        } else {
+                            Arity::Fixed(4)
+                        },
+                        args.len(),
+                    )),
+                }
+            }),
+        ),
+        (
+            "partition-all".to_string(),
+            // partitionと同じだが、n個に満たない最後の部分も残す. (partition-all n) はtransducer
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [MalVal::Number(n)] if *n > 0 => Ok(partition_all_xf(*n as usize)),
+                [MalVal::Number(n), coll] if *n > 0 => Ok(partition(
+                    *n as usize,
+                    *n as usize,
+                    Some(MalVal::Nil),
+                    coll.clone(),
+                )),
+                [n] | [n, _] => Err(MalError::InvalidType(
+                    n.clone(),
+                    "positive integer".to_string(),
+                )),
+                _ => Err(MalError::WrongArity(
+                    "partition-all".to_string(),
+                    Arity::JustOrOneLess(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "transduce".to_string(),
+            // (transduce xform f coll) または (transduce xform f init coll)
+            // initがなければ (f) を初期値とする
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [xform, f, coll] => transduce(xform, f.clone(), f.apply(vec![])?, coll),
+                [xform, f, init, coll] => transduce(xform, f.clone(), init.clone(), coll),
+                _ => Err(MalError::WrongArity(
+                    "transduce".to_string(),
+                    Arity::JustOrOneLess(4),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "completing".to_string(),
+            // 二引数の関数fを還元関数にする. 完了時は (cf acc) を返し、cfがなければaccを返す
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [f] | [f, _] => {
+                    let (f, cf) = (f.clone(), args.get(1).cloned());
+                    Ok(MalVal::native("completing", move |mut args| {
+                        match (args.len(), &cf) {
+                            (1, Some(cf)) => cf.apply(args),
+                            (1, None) => Ok(args.pop().unwrap()),
+                            _ => f.apply(args),
+                        }
+                    }))
+                }
+                _ => Err(MalError::WrongArity(
+                    "completing".to_string(),
+                    Arity::JustOrOneLess(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "sequence".to_string(),
+            // xformを通した要素の遅延シーケンス
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [coll] => seq(coll).map(|xs| match xs {
+                    MalVal::Nil => MalVal::list(vec![]),
+                    xs => xs,
+                }),
+                [xform, coll] => eduction(xform, coll.clone()),
+                _ => Err(MalError::WrongArity(
+                    "sequence".to_string(),
+                    Arity::JustOrOneLess(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "eduction".to_string(),
+            // (eduction xform... coll): xformを左から順に通した要素の遅延シーケンス
+            // NOTE: Clojureと異なり、計算した要素は記憶する
+            MalVal::BuiltinFn(|mut args| {
+                let Some(coll) = args.pop() else {
+                    return Err(MalError::WrongArity(
+                        "eduction".to_string(),
+                        Arity::Variadic(1),
+                        0,
+                    ));
+                };
+                let xform = match args.len() {
+                    0 => MalVal::BuiltinFn(identity),
+                    1 => args.pop().unwrap(),
+                    _ => comp(args),
+                };
+                eduction(&xform, coll)
+            }),
+        ),
+        (
+            "partition-by".to_string(),
+            // (f x) の値が変わるところで区切る
+            MalVal::BuiltinFn(|args| match <[MalVal; 2]>::try_from(args) {
+                Ok([f, coll]) => Ok(partition_by(f, coll)),
+                Err(args) => Err(MalError::WrongArity(
+                    "partition-by".to_string(),
+                    Arity::Fixed(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "group-by".to_string(),
+            // (f x) -> xのベクタ のマップ
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [f, coll] => {
+                    let mut groups: HashMap<MalVal, Vector<MalVal>, FnvBuildHasher> =
+                        HashMap::default();
+                    for x in seq_of(coll)? {
+                        let x = x?;
+                        groups
+                            .entry(f.apply(vec![x.clone()])?)
+                            .or_default()
+                            .push_back(x);
+                    }
+                    Ok(MalVal::hashmap(
+                        groups
+                            .into_iter()
+                            .map(|(k, v)| (k, MalVal::vec(v)))
+                            .collect(),
+                    ))
+                }
+                _ => Err(MalError::WrongArity(
+                    "group-by".to_string(),
+                    Arity::Fixed(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "frequencies".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [coll] => {
+                    let mut counts: HashMap<MalVal, i64, FnvBuildHasher> = HashMap::default();
+                    for x in seq_of(coll)? {
+                        *counts.entry(x?).or_default() += 1;
+                    }
+                    Ok(MalVal::hashmap(
+                        counts
+                            .into_iter()
+                            .map(|(k, n)| (k, MalVal::Number(n)))
+                            .collect(),
+                    ))
+                }
+                _ => Err(MalError::WrongArity(
+                    "frequencies".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "distinct".to_string(),
+            // 最初に現れた順に重複を除く
+            MalVal::BuiltinFn(|args| match <[MalVal; 1]>::try_from(args) {
+                Ok([coll]) => Ok(distinct(coll, HashSet::default())),
+                Err(args) => Err(MalError::WrongArity(
+                    "distinct".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "zipmap".to_string(),
+            // 短い方に合わせる
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [keys, vals] => {
+                    let m: HashMap<_, _, _> = seq_of(keys)?
+                        .zip(seq_of(vals)?)
+                        .map(|(k, v)| Ok::<_, MalError>((k?, v?)))
+                        .try_collect()?;
+                    Ok(MalVal::hashmap(m))
+                }
+                _ => Err(MalError::WrongArity(
+                    "zipmap".to_string(),
+                    Arity::Fixed(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        ("identity".to_string(), MalVal::BuiltinFn(identity)),
+        (
+            "comp".to_string(),
+            // 右から順に適用する. 最も右の関数は任意の数の引数を取れる
+            MalVal::BuiltinFn(|args| match args.len() {
+                0 => Ok(MalVal::BuiltinFn(identity)),
+                1 => Ok(args[0].clone()),
+                _ => Ok(comp(args)),
+            }),
+        ),
+        (
+            "partial".to_string(),
+            // 引数の前半を固定した関数
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [] => Err(MalError::WrongArity(
+                    "partial".to_string(),
+                    Arity::Variadic(1),
+                    0,
+                )),
+                [f] => Ok(f.clone()),
+                [f, fixed @ ..] => {
+                    let (f, fixed) = (f.clone(), fixed.to_vec());
+                    Ok(MalVal::native("partial", move |args| {
+                        f.apply(fixed.iter().cloned().chain(args).collect())
+                    }))
+                }
+            }),
+        ),
+        (
+            "juxt".to_string(),
+            // 各関数を同じ引数に適用した結果のベクタを返す関数
+            MalVal::BuiltinFn(|args| {
+                if args.is_empty() {
+                    return Err(MalError::WrongArity(
+                        "juxt".to_string(),
+                        Arity::Variadic(1),
+                        0,
+                    ));
+                }
+                Ok(MalVal::native("juxt", move |xs| {
+                    let ys: Vector<_> = args.iter().map(|f| f.apply(xs.clone())).try_collect()?;
+                    Ok(MalVal::vec(ys))
+                }))
+            }),
+        ),
+        (
+            "complement".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [f] => {
+                    let f = f.clone();
+                    Ok(MalVal::native("complement", move |args| {
+                        Ok(MalVal::Bool(!truthy(&f.apply(args)?)))
+                    }))
+                }
+                _ => Err(MalError::WrongArity(
+                    "complement".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "constantly".to_string(),
+            // 引数によらず常にxを返す関数
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [x] => {
+                    let x = x.clone();
+                    Ok(MalVal::native("constantly", move |_| Ok(x.clone())))
+                }
+                _ => Err(MalError::WrongArity(
+                    "constantly".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "set-print-length!".to_string(),
+            // 遅延シーケンスを表示する最大の要素数. nilなら制限しない
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [MalVal::Nil] => {
+                    printer::set_print_length(None);
+                    Ok(MalVal::Nil)
+                }
+                [MalVal::Number(n)] => {
+                    printer::set_print_length(Some((*n).max(0) as usize));
+                    Ok(MalVal::Nil)
+                }
+                [x] => Err(MalError::InvalidType(
+                    x.clone(),
+                    "integer or nil".to_string(),
+                )),
+                _ => Err(MalError::WrongArity(
+                    "set-print-length!".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "=".to_string(),
+            MalVal::BuiltinFn(|args| Ok(MalVal::Bool(args.into_iter().all_equal()))),
+        ),
+        ("<".to_string(), mal_cmp!(Ordering::is_lt)),
+        ("<=".to_string(), mal_cmp!(Ordering::is_le)),
+        (">".to_string(), mal_cmp!(Ordering::is_gt)),
+        (">=".to_string(), mal_cmp!(Ordering::is_ge)),
+        (
+            "compare".to_string(),
+            // 数値同士はコレクションの要素も含めて値で比較し、1と1.0は等しいとする
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [a, b] => Ok(MalVal::Number(a.value_cmp(b) as i64)),
+                _ => Err(MalError::WrongArity(
+                    "compare".to_string(),
+                    Arity::Fixed(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "sort".to_string(),
+            // (sort coll) または (sort comparator coll)
+            MalVal::BuiltinFn(|args| {
+                let (comparator, coll) = match args.as_slice() {
+                    [coll] => (None, coll),
+                    [f, coll] => (Some(f), coll),
+                    _ => {
+                        return Err(MalError::WrongArity(
+                            "sort".to_string(),
+                            Arity::JustOrOneLess(2),
+                            args.len(),
+                        ))
+                    }
+                };
+                sorted::sort_by(seq_items(coll)?, &mut |a, b| sort_compare(comparator, a, b))
+                    .map(MalVal::list)
+            }),
+        ),
+        (
+            "sort-by".to_string(),
+            // (sort-by keyfn coll) または (sort-by keyfn comparator coll)
+            MalVal::BuiltinFn(|args| {
+                let (keyfn, comparator, coll) = match args.as_slice() {
+                    [k, coll] => (k, None, coll),
+                    [k, f, coll] => (k, Some(f), coll),
+                    _ => {
+                        return Err(MalError::WrongArity(
+                            "sort-by".to_string(),
+                            Arity::JustOrOneLess(3),
+                            args.len(),
+                        ))
+                    }
+                };
+                // キーは要素ごとに一度だけ計算する
+                let keyed: Vec<_> = seq_items(coll)?
+                    .into_iter()
+                    .map(|x| Ok::<_, MalError>((keyfn.apply(vec![x.clone()])?, x)))
+                    .try_collect()?;
+                let keyed =
+                    sorted::sort_by(keyed, &mut |a, b| sort_compare(comparator, &a.0, &b.0))?;
+                Ok(MalVal::list(keyed.into_iter().map(|(_, x)| x).collect()))
+            }),
+        ),
+        (
+            "min-key".to_string(),
+            MalVal::BuiltinFn(|args| extreme_key("min-key", args, Ordering::Less)),
+        ),
+        (
+            "max-key".to_string(),
+            MalVal::BuiltinFn(|args| extreme_key("max-key", args, Ordering::Greater)),
+        ),
+        (
+            "number?".to_string(),
+            MalVal::BuiltinFn(|args| {
+                Ok(MalVal::Bool(matches!(
+                    args.first(),
+                    Some(
+                        MalVal::Number(_) | MalVal::BigInt(_) | MalVal::Float(_) | MalVal::Ratio(_)
+                    )
+                )))
+            }),
+        ),
+        (
+            "int?".to_string(),
+            MalVal::BuiltinFn(|args| {
+                Ok(MalVal::Bool(matches!(
+                    args.first(),
+                    Some(MalVal::Number(_) | MalVal::BigInt(_))
+                )))
+            }),
+        ),
+        (
+            "float?".to_string(),
+            MalVal::BuiltinFn(|args| {
+                Ok(MalVal::Bool(matches!(args.first(), Some(MalVal::Float(_)))))
+            }),
+        ),
+        (
+            "ratio?".to_string(),
+            MalVal::BuiltinFn(|args| {
+                Ok(MalVal::Bool(matches!(args.first(), Some(MalVal::Ratio(_)))))
+            }),
+        ),
+        (
+            "rational?".to_string(),
+            MalVal::BuiltinFn(|args| {
+                Ok(MalVal::Bool(
+                    args.first().and_then(MalVal::to_rational).is_some(),
+                ))
+            }),
+        ),
+        (
+            "numerator".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [x] => x
+                    .to_rational()
+                    .map(|r| MalVal::from(r.numer().clone()))
+                    .ok_or(MalError::InvalidType(x.clone(), "rational".to_string())),
+                _ => Err(MalError::WrongArity(
+                    "numerator".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "denominator".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [x] => x
+                    .to_rational()
+                    .map(|r| MalVal::from(r.denom().clone()))
+                    .ok_or(MalError::InvalidType(x.clone(), "rational".to_string())),
+                _ => Err(MalError::WrongArity(
+                    "denominator".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "rationalize".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [MalVal::Float(f)] => {
+                    rationalize(*f)
+                        .map(MalVal::from)
+                        .ok_or(MalError::InvalidType(
+                            MalVal::Float(*f),
+                            "finite number".to_string(),
+                        ))
+                }
+                [x @ (MalVal::Number(_) | MalVal::BigInt(_) | MalVal::Ratio(_))] => Ok(x.clone()),
+                [x] => Err(MalError::InvalidType(x.clone(), "number".to_string())),
+                _ => Err(MalError::WrongArity(
+                    "rationalize".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "double".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [x] => x
+                    .to_f64()
+                    .map(MalVal::Float)
+                    .ok_or(MalError::InvalidType(x.clone(), "number".to_string())),
+                _ => Err(MalError::WrongArity(
+                    "double".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "long".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                // 浮動小数点数は0の方向に切り捨てる
+                [MalVal::Float(f)] => {
+                    BigInt::from_f64(f.trunc())
+                        .map(MalVal::from)
+                        .ok_or(MalError::InvalidType(
+                            MalVal::Float(*f),
+                            "finite number".to_string(),
+                        ))
+                }
+                [MalVal::Ratio(r)] => Ok(MalVal::from(r.to_integer())),
+                [x @ (MalVal::Number(_) | MalVal::BigInt(_))] => Ok(x.clone()),
+                [x] => Err(MalError::InvalidType(x.clone(), "number".to_string())),
+                _ => Err(MalError::WrongArity(
+                    "long".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "quot".to_string(),
+            // 0の方向に切り捨てた商
+            MalVal::BuiltinFn(|args| {
+                binary_op(
+                    "quot",
+                    args,
+                    i64::checked_div,
+                    |a, b| Ok((a / b).trunc()),
+                    |a, b| (a / b).trunc(),
+                )
+            }),
+        ),
+        (
+            "rem".to_string(),
+            // 余りの符号は割られる数に合わせる
+            MalVal::BuiltinFn(|args| {
+                binary_op(
+                    "rem",
+                    args,
+                    i64::checked_rem,
+                    |a, b| Ok(a % b),
+                    |a, b| a % b,
+                )
+            }),
+        ),
+        (
+            "mod".to_string(),
+            // 余りの符号は割る数に合わせる
+            MalVal::BuiltinFn(|args| {
+                binary_op(
+                    "mod",
+                    args,
+                    |a, b| {
+                        a.checked_rem(b).map(|r| {
+                            if r != 0 && (r < 0) != (b < 0) {
+                                r + b
+                            } else {
+                                r
+                            }
+                        })
+                    },
+                    |a, b| {
+                        let r = a % &b;
+                        Ok(if !r.is_zero() && r.is_negative() != b.is_negative() {
+                            r + b
+                        } else {
+                            r
+                        })
+                    },
+                    |a, b| {
+                        let r = a % b;
+                        if r != 0.0 && (r < 0.0) != (b < 0.0) {
+                            r + b
+                        } else {
+                            r
+                        }
+                    },
+                )
+            }),
+        ),
+        (
+            "inc".to_string(),
+            MalVal::BuiltinFn(|args| match <[MalVal; 1]>::try_from(args) {
+                Ok([x]) => num_op(
+                    x,
+                    MalVal::Number(1),
+                    i64::checked_add,
+                    |a, b| Ok(a + b),
+                    |a, b| a + b,
+                ),
+                Err(args) => Err(MalError::WrongArity(
+                    "inc".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "dec".to_string(),
+            MalVal::BuiltinFn(|args| match <[MalVal; 1]>::try_from(args) {
+                Ok([x]) => num_op(
+                    x,
+                    MalVal::Number(1),
+                    i64::checked_sub,
+                    |a, b| Ok(a - b),
+                    |a, b| a - b,
+                ),
+                Err(args) => Err(MalError::WrongArity(
+                    "dec".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "abs".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [MalVal::Number(n)] => Ok(n
+                    .checked_abs()
+                    .map(MalVal::Number)
+                    .unwrap_or_else(|| MalVal::from(-BigInt::from(*n)))),
+                [MalVal::Float(f)] => Ok(MalVal::Float(f.abs())),
+                [x] => x
+                    .to_rational()
+                    .map(|r| MalVal::from(r.abs()))
+                    .ok_or(MalError::InvalidType(x.clone(), "number".to_string())),
+                _ => Err(MalError::WrongArity(
+                    "abs".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "min".to_string(),
+            MalVal::BuiltinFn(|args| num_extreme("min", args, Ordering::Less)),
+        ),
+        (
+            "max".to_string(),
+            MalVal::BuiltinFn(|args| num_extreme("max", args, Ordering::Greater)),
+        ),
+        (
+            "pow".to_string(),
+            // 指数が整数なら正確に計算する. それ以外は浮動小数点数になる
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [base, exp] => match (base.to_rational(), exp.to_bigint()) {
+                    (Some(b), Some(e)) => rational_pow(b, &e).map(MalVal::from),
+                    _ => match (base.to_f64(), exp.to_f64()) {
+                        (Some(b), Some(e)) => Ok(MalVal::Float(b.powf(e))),
+                        (None, _) => Err(MalError::InvalidType(base.clone(), "number".to_string())),
+                        _ => Err(MalError::InvalidType(exp.clone(), "number".to_string())),
+                    },
+                },
+                _ => Err(MalError::WrongArity(
+                    "pow".to_string(),
+                    Arity::Fixed(2),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "sqrt".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [x] => x
+                    .to_f64()
+                    .map(|f| MalVal::Float(f.sqrt()))
+                    .ok_or(MalError::InvalidType(x.clone(), "number".to_string())),
+                _ => Err(MalError::WrongArity(
+                    "sqrt".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "bit-and".to_string(),
+            MalVal::BuiltinFn(|args| bit_op("bit-and", args, |a, b| a & b)),
+        ),
+        (
+            "bit-or".to_string(),
+            MalVal::BuiltinFn(|args| bit_op("bit-or", args, |a, b| a | b)),
+        ),
+        (
+            "bit-xor".to_string(),
+            MalVal::BuiltinFn(|args| bit_op("bit-xor", args, |a, b| a ^ b)),
+        ),
+        (
+            "bit-shift-left".to_string(),
+            // 桁あふれする場合は多倍長整数になる
+            MalVal::BuiltinFn(|args| {
+                let (x, n) = shift_args("bit-shift-left", &args)?;
+                if !x.is_zero() && x.bits().saturating_add(n) > MAX_BITS {
+                    return Err(MalError::Overflow("bit-shift-left".to_string()));
+                }
+                Ok(MalVal::from(x << n))
+            }),
+        ),
+        (
+            "bit-shift-right".to_string(),
+            // 算術シフト: 負の数は負の無限大の方向に丸める
+            MalVal::BuiltinFn(|args| {
+                let (x, n) = shift_args("bit-shift-right", &args)?;
+                Ok(MalVal::from(x >> n))
+            }),
+        ),
+        (
+            "zero?".to_string(),
+            MalVal::BuiltinFn(|args| sign_is("zero?", args, Ordering::Equal)),
+        ),
+        (
+            "pos?".to_string(),
+            MalVal::BuiltinFn(|args| sign_is("pos?", args, Ordering::Greater)),
+        ),
+        (
+            "neg?".to_string(),
+            MalVal::BuiltinFn(|args| sign_is("neg?", args, Ordering::Less)),
+        ),
+        (
+            "even?".to_string(),
+            MalVal::BuiltinFn(|args| is_odd("even?", args).map(|odd| MalVal::Bool(!odd))),
+        ),
+        (
+            "odd?".to_string(),
+            MalVal::BuiltinFn(|args| is_odd("odd?", args).map(MalVal::Bool)),
+        ),
+        (
+            "pr-str".to_string(),
+            MalVal::BuiltinFn(|args| {
+                let xs: Vec<_> = args
+                    .iter()
+                    .map(|x| printer::try_pr_str(x, true))
+                    .try_collect()?;
+                Ok(MalVal::string(xs.join(" ")))
+            }),
+        ),
+        (
+            "str".to_string(),
+            MalVal::BuiltinFn(|args| {
+                let xs: Vec<_> = args
+                    .iter()
+                    .map(|x| printer::try_pr_str(x, false))
+                    .try_collect()?;
+                Ok(MalVal::string(xs.concat()))
+            }),
+        ),
+        (
+            "prn".to_string(),
+            MalVal::BuiltinFn(|args| {
+                let xs: Vec<_> = args
+                    .iter()
+                    .map(|x| printer::try_pr_str(x, true))
+                    .try_collect()?;
+                fast_print(xs.into_iter());
+                Ok(MalVal::Nil)
+            }),
+        ),
+        (
+            "println".to_string(),
+            MalVal::BuiltinFn(|args| {
+                let xs: Vec<_> = args
+                    .iter()
+                    .map(|x| printer::try_pr_str(x, false))
+                    .try_collect()?;
+                fast_print(xs.into_iter());
+                Ok(MalVal::Nil)
+            }),
+        ),
+        (
+            "subs".to_string(),
+            // 添字は文字単位
+            MalVal::BuiltinFn(|args| {
+                let (s, start, end) = match args.as_slice() {
+                    [s, start] => (s, start, None),
+                    [s, start, end] => (s, start, Some(end)),
+                    _ => {
+                        return Err(MalError::WrongArity(
+                            "subs".to_string(),
+                            Arity::JustOrOneLess(3),
+                            args.len(),
+                        ))
+                    }
+                };
+                let s = as_str(s)?;
+                let len = s.chars().count();
+                let index = |x: &MalVal, min: usize| match x {
+                    MalVal::Number(i) if (min as i64..=len as i64).contains(i) => Ok(*i as usize),
+                    MalVal::Number(i) => Err(MalError::IndexOutOfBounds(*i, len)),
+                    _ => Err(MalError::InvalidType(x.clone(), "integer".to_string())),
+                };
+                let start = index(start, 0)?;
+                let end = end.map(|end| index(end, start)).transpose()?.unwrap_or(len);
+                Ok(MalVal::string(
+                    s.chars().skip(start).take(end - start).collect::<String>(),
+                ))
+            }),
+        ),
+        (
+            "split".to_string(),
+            // 区切りは文字列か正規表現. 末尾の空文字列は取り除く
+            // 区切りが空文字列なら一文字ずつに分ける
+            MalVal::BuiltinFn(|args| {
+                let mut parts: Vector<_> = match args.as_slice() {
+                    [s, MalVal::Regex(re)] => {
+                        let s = as_str(s)?;
+                        let mut parts: Vector<_> = re.split(s).map(MalVal::string).collect();
+                        // 先頭の幅0のマッチでは区切らない
+                        if re.find(s).is_some_and(|m| m.is_empty() && m.start() == 0) {
+                            parts.pop_front();
+                        }
+                        parts
+                    }
+                    _ => match str_args("split", &args)? {
+                        [s, ""] => s.chars().map(MalVal::string).collect(),
+                        [s, sep] => s.split(sep).map(MalVal::string).collect(),
+                    },
+                };
+                while parts.len() > 1 && parts.back() == Some(&MalVal::string("")) {
+                    parts.pop_back();
+                }
+                Ok(MalVal::vec(parts))
+            }),
+        ),
+        (
+            "join".to_string(),
+            // (join coll) または (join separator coll)
+            MalVal::BuiltinFn(|args| {
+                let (sep, coll) = match args.as_slice() {
+                    [coll] => ("", coll),
+                    [sep, coll] => (as_str(sep)?, coll),
+                    _ => {
+                        return Err(MalError::WrongArity(
+                            "join".to_string(),
+                            Arity::JustOrOneLess(2),
+                            args.len(),
+                        ))
+                    }
+                };
+                let xs: Vec<_> = seq_of(coll)?
+                    .map(|x| printer::try_pr_str(&x?, false))
+                    .try_collect()?;
+                Ok(MalVal::string(xs.join(sep)))
+            }),
+        ),
+        (
+            "trim".to_string(),
+            MalVal::BuiltinFn(|args| str_map("trim", args, |s| s.trim().to_string())),
+        ),
+        (
+            "triml".to_string(),
+            MalVal::BuiltinFn(|args| str_map("triml", args, |s| s.trim_start().to_string())),
+        ),
+        (
+            "trimr".to_string(),
+            MalVal::BuiltinFn(|args| str_map("trimr", args, |s| s.trim_end().to_string())),
+        ),
+        (
+            "upper-case".to_string(),
+            MalVal::BuiltinFn(|args| str_map("upper-case", args, str::to_uppercase)),
+        ),
+        (
+            "lower-case".to_string(),
+            MalVal::BuiltinFn(|args| str_map("lower-case", args, str::to_lowercase)),
+        ),
+        (
+            "starts-with?".to_string(),
+            MalVal::BuiltinFn(|args| str_test("starts-with?", args, |s, sub| s.starts_with(sub))),
+        ),
+        (
+            "ends-with?".to_string(),
+            MalVal::BuiltinFn(|args| str_test("ends-with?", args, |s, sub| s.ends_with(sub))),
+        ),
+        (
+            "includes?".to_string(),
+            MalVal::BuiltinFn(|args| str_test("includes?", args, |s, sub| s.contains(sub))),
+        ),
+        (
+            "index-of".to_string(),
+            // (index-of s value from?): 見つかった位置を文字単位で返す. 見つからなければnil
+            MalVal::BuiltinFn(|args| {
+                let (s, value, from) = match args.as_slice() {
+                    [s, value] => (as_str(s)?, as_str(value)?, 0),
+                    [s, value, MalVal::Number(from)] => {
+                        (as_str(s)?, as_str(value)?, (*from).max(0) as usize)
+                    }
+                    [_, _, from] => {
+                        return Err(MalError::InvalidType(from.clone(), "integer".to_string()))
+                    }
+                    _ => {
+                        return Err(MalError::WrongArity(
+                            "index-of".to_string(),
+                            Arity::JustOrOneLess(3),
+                            args.len(),
+                        ))
+                    }
+                };
+                let Some(start) = byte_offset(s, from) else {
+                    return Ok(MalVal::Nil);
+                };
+                Ok(s[start..]
+                    .find(value)
+                    .map(|i| MalVal::Number((from + s[start..start + i].chars().count()) as i64))
+                    .unwrap_or(MalVal::Nil))
+            }),
+        ),
+        (
+            "replace".to_string(),
+            // 一致する部分を全て置き換える. 正規表現の場合、置換後の文字列の$1はグループを表す
+            // 置換後の値が関数なら、マッチしたグループを渡して呼び出した結果に置き換える
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [s, MalVal::Regex(re), MalVal::String(to)] => {
+                    Ok(MalVal::string(re.replace_all(as_str(s)?, to.as_str())))
+                }
+                [s, MalVal::Regex(re), f] => {
+                    let mut err = None;
+                    let replaced = re.replace_all(as_str(s)?, |caps: &Captures| {
+                        match f.apply(vec![match_groups(caps)]) {
+                            Ok(x) => printer::pr_str(&x, false),
+                            Err(e) => {
+                                err.get_or_insert(e);
+                                String::new()
+                            }
+                        }
+                    });
+                    match err {
+                        Some(e) => Err(e),
+                        None => Ok(MalVal::string(replaced)),
+                    }
+                }
+                _ => {
+                    let [s, from, to] = str_args("replace", &args)?;
+                    Ok(MalVal::string(s.replace(from, to)))
+                }
+            }),
+        ),
+        (
+            "reverse".to_string(),
+            // 文字列は逆順の文字列、それ以外は逆順のリストにする
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [MalVal::String(s)] => Ok(MalVal::string(s.chars().rev().collect::<String>())),
+                [coll] => Ok(MalVal::list(seq_items(coll)?.into_iter().rev().collect())),
+                _ => Err(MalError::WrongArity(
+                    "reverse".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "blank?".to_string(),
+            // nilと空白文字だけの文字列は空とみなす
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [MalVal::Nil] => Ok(MalVal::Bool(true)),
+                [x] => Ok(MalVal::Bool(as_str(x)?.trim().is_empty())),
+                _ => Err(MalError::WrongArity(
+                    "blank?".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "re-pattern".to_string(),
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [re @ MalVal::Regex(_)] => Ok(re.clone()),
+                [s] => MalVal::regex(as_str(s)?),
+                _ => Err(MalError::WrongArity(
+                    "re-pattern".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "re-find".to_string(),
+            // 最初のマッチ. グループがあれば [全体 グループ1 ...] のベクタ
+            MalVal::BuiltinFn(|args| {
+                let (re, s) = regex_args("re-find", &args)?;
+                Ok(re
+                    .captures(s)
+                    .map_or(MalVal::Nil, |caps| match_groups(&caps)))
+            }),
+        ),
+        (
+            "re-matches".to_string(),
+            // 文字列全体がマッチする場合だけre-findと同じ値を返す
+            MalVal::BuiltinFn(|args| {
+                let (re, s) = regex_args("re-matches", &args)?;
+                let whole = cached_regex(&format!("^(?:{})$", re.as_str()))?;
+                Ok(whole
+                    .captures(s)
+                    .map_or(MalVal::Nil, |caps| match_groups(&caps)))
+            }),
+        ),
+        (
+            "re-seq".to_string(),
+            // 全てのマッチのリスト. マッチしなければnil
+            MalVal::BuiltinFn(|args| {
+                let (re, s) = regex_args("re-seq", &args)?;
+                let matches = re
+                    .captures_iter(s)
+                    .map(|caps| match_groups(&caps))
+                    .collect_vec();
+                Ok(if matches.is_empty() {
+                    MalVal::Nil
+                } else {
+                    MalVal::list(matches)
+                })
+            }),
+        ),
+        (
+            "re-groups".to_string(),
+            // 最初のマッチのグループを、グループがなくても常に [全体 グループ1 ...] のベクタで返す
+            MalVal::BuiltinFn(|args| {
+                let (re, s) = regex_args("re-groups", &args)?;
+                Ok(re.captures(s).map_or(MalVal::Nil, |caps| group_vec(&caps)))
+            }),
+        ),
+        (
+            "format".to_string(),
+            MalVal::BuiltinFn(|args| match args.split_first() {
+                Some((fmt, args)) => printer::format(as_str(fmt)?, args).map(MalVal::string),
+                None => Err(MalError::WrongArity(
+                    "format".to_string(),
+                    Arity::Variadic(1),
+                    0,
+                )),
+            }),
+        ),
+        (
+            "printf".to_string(),
+            // formatの結果を改行せずに出力する
+            MalVal::BuiltinFn(|args| match args.split_first() {
+                Some((fmt, args)) => {
+                    let s = printer::format(as_str(fmt)?, args)?;
+                    let mut out = io::stdout().lock();
+                    out.write_all(s.as_bytes()).unwrap();
+                    out.flush().unwrap();
+                    Ok(MalVal::Nil)
+                }
+                None => Err(MalError::WrongArity(
+                    "printf".to_string(),
+                    Arity::Variadic(1),
+                    0,
+                )),
+            }),
+        ),
+        (
+            "throw".to_string(),
+            MalVal::BuiltinFn(|args| match <[MalVal; 1]>::try_from(args) {
+                Ok([val]) => Err(MalError::from_val(val)),
+                Err(args) => Err(MalError::WrongArity(
+                    "throw".to_string(),
+                    Arity::Fixed(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "invoke-restart".to_string(),
+            // 最も内側にある同名のrestart-caseまで巻き戻す
+            // 有効なリスタートがなければcatch*で捕捉できる通常のエラーにする
+            MalVal::BuiltinFn(|mut args| match args.first() {
+                Some(k @ MalVal::Keyword(name)) => {
+                    if RESTARTS.with_borrow(|restarts| restarts.contains(k)) {
+                        Err(MalError::Restart(name.to_string(), args.split_off(1)))
+                    } else {
+                        Err(MalError::NoRestart(name.to_string()))
+                    }
+                }
+                Some(z) => Err(MalError::InvalidType(z.clone(), "keyword".to_string())),
+                None => Err(MalError::WrongArity(
+                    "invoke-restart".to_string(),
+                    Arity::Variadic(1),
+                    0,
+                )),
+            }),
+        ),
+        (
+            "ex-info".to_string(),
+            MalVal::BuiltinFn(|mut args| {
+                if args.len() < 2 || args.len() > 3 {
+                    return Err(MalError::WrongArity(
+                        "ex-info".to_string(),
+                        Arity::JustOrOneLess(3),
+                        args.len(),
+                    ));
+                }
+                let cause = (args.len() == 3).then(|| args.pop()).flatten();
+                let data = args.pop().unwrap_or(MalVal::Nil);
+                let err = match args.pop() {
+                    Some(MalVal::String(msg)) => MalError::Info(msg.to_string(), data),
+                    Some(z) => return Err(MalError::InvalidType(z, "string".to_string())),
+                    None => unreachable!(),
+                };
+                Ok(MalVal::Error(Rc::new(match cause {
+                    Some(MalVal::Nil) | None => err,
+                    Some(cause) => err.with_cause(MalError::from_val(cause)),
+                })))
+            }),
+        ),
+        (
+            "ex-message".to_string(),
+            MalVal::BuiltinFn(|args| match args.first() {
+                Some(MalVal::Error(e)) => Ok(MalVal::string(e.to_string())),
+                _ => Ok(MalVal::Nil),
+            }),
+        ),
+        (
+            "ex-data".to_string(),
+            // ex-infoのデータ. それ以外のエラーはエラー自体をハッシュマップにしたもの
+            MalVal::BuiltinFn(|args| match args.first() {
+                Some(MalVal::Error(e)) => Ok(e.data().cloned().unwrap_or_else(|| e.to_mal())),
+                _ => Ok(MalVal::Nil),
+            }),
+        ),
+        (
+            "ex-trace".to_string(),
+            // エラーが発生した時点の呼び出しスタック. 引数がなければcatch*で捕捉中のエラーのもの
+            // throwされた値は catch* でそのまま束縛されるので、引数なしで取り出す
+            MalVal::BuiltinFn(|args| match args.as_slice() {
+                [] => {
+                    Ok(CAUGHT
+                        .with_borrow(|caught| caught.last().map_or(MalVal::Nil, MalError::trace)))
+                }
+                [MalVal::Error(e)] => Ok(e.trace()),
+                [_] => Ok(MalVal::Nil),
+                _ => Err(MalError::WrongArity(
+                    "ex-trace".to_string(),
+                    Arity::JustOrOneLess(1),
+                    args.len(),
+                )),
+            }),
+        ),
+        (
+            "ex-cause".to_string(),
+            MalVal::BuiltinFn(|args| match args.first() {
+                Some(MalVal::Error(e)) => Ok(match e.untraced() {
+                    MalError::Caused(_, cause) => MalVal::from_error(cause.as_ref().clone()),
+                    _ => MalVal::Nil,
+                }),
+                _ => Ok(MalVal::Nil),
+            }),
+        ),
+    ]
+    .into()
+}
+
+/// 数値の二項演算
+/// intはi64での計算で、オーバーフローした場合はNoneを返す
+/// その場合と多倍長整数、分数を含む場合はratioで分数として正確に計算し直す (整数になれば整数に戻る)
+/// どちらかが浮動小数点数の場合はfloatで浮動小数点数として計算する
+pub fn num_op(
+    a: MalVal,
+    b: MalVal,
+    int: fn(i64, i64) -> Option<i64>,
+    ratio: fn(BigRational, BigRational) -> Result<BigRational, MalError>,
+    float: fn(f64, f64) -> f64,
+) -> MalResult {
+    if let (MalVal::Number(a), MalVal::Number(b)) = (&a, &b) {
+        if let Some(n) = int(*a, *b) {
+            return Ok(MalVal::Number(n));
+        }
+    }
+    match (a.to_rational(), b.to_rational()) {
+        (Some(a), Some(b)) => ratio(a, b).map(MalVal::from),
+        _ => match (a.to_f64(), b.to_f64()) {
+            (Some(a), Some(b)) => Ok(MalVal::Float(float(a, b))),
+            (None, _) => Err(MalError::InvalidType(a, "number".to_string())),
+            _ => Err(MalError::InvalidType(b, "number".to_string())),
+        },
+    }
+}
+
+// 二引数の数値演算. 計算の規則はnum_opと同じ
+fn binary_op(
+    name: &str,
+    args: Vec<MalVal>,
+    int: fn(i64, i64) -> Option<i64>,
+    ratio: fn(BigRational, BigRational) -> Result<BigRational, MalError>,
+    float: fn(f64, f64) -> f64,
+) -> MalResult {
+    match <[MalVal; 2]>::try_from(args) {
+        // 整数の除算なので、浮動小数点数でも0で割った場合は##Infや##NaNにせずエラーにする
+        Ok([a, b]) if a.to_f64().is_some() && b.to_f64() == Some(0.0) => {
+            Err(MalError::DividedByZero)
+        }
+        Ok([a, b]) => num_op(a, b, int, ratio, float),
+        Err(args) => Err(MalError::WrongArity(
+            name.to_string(),
+            Arity::Fixed(2),
+            args.len(),
+        )),
+    }
+}
+
+// minとmax: keepの側にある値を返す. NaNが含まれる場合はNaN
+fn num_extreme(name: &str, args: Vec<MalVal>, keep: Ordering) -> MalResult {
+    if let Some(x) = args.iter().find(|x| x.to_f64().is_none()) {
+        return Err(MalError::InvalidType(x.clone(), "number".to_string()));
+    }
+    if let Some(nan) = args
+        .iter()
+        .find(|x| matches!(x, MalVal::Float(f) if f.is_nan()))
+    {
+        return Ok(nan.clone());
+    }
+    args.into_iter()
+        .reduce(|acc, x| {
+            if acc.num_cmp(&x) == Some(keep.reverse()) {
+                x
+            } else {
+                acc
+            }
+        })
+        .ok_or(MalError::WrongArity(
+            name.to_string(),
+            Arity::Variadic(1),
+            0,
+        ))
+}
+
+// これより多くのビットを必要とする整数は作らずにOverflowにする
+const MAX_BITS: u64 = 1 << 20;
+
+// 分数の整数乗
+fn rational_pow(base: BigRational, exp: &BigInt) -> Result<BigRational, MalError> {
+    if base.is_zero() {
+        return match exp.sign() {
+            Sign::Minus => Err(MalError::DividedByZero),
+            Sign::NoSign => Ok(BigRational::one()),
+            Sign::Plus => Ok(base),
+        };
+    }
+    let bits = base.numer().bits().max(base.denom().bits());
+    // 1と-1は指数の偶奇だけで決まる
+    if bits == 1 {
+        return Ok(if exp.bit(0) { base } else { BigRational::one() });
+    }
+    match exp.to_i32() {
+        Some(e) if bits.saturating_mul(e.unsigned_abs() as u64) <= MAX_BITS => Ok(base.pow(e)),
+        _ => Err(MalError::Overflow("pow".to_string())),
+    }
+}
+
+// 整数を可変長引数で受け取り、ビット演算で畳み込む
+fn bit_op(name: &str, args: Vec<MalVal>, op: fn(BigInt, BigInt) -> BigInt) -> MalResult {
+    if args.len() < 2 {
+        return Err(MalError::WrongArity(
+            name.to_string(),
+            Arity::Variadic(2),
+            args.len(),
+        ));
+    }
+    let ints: Vec<_> = args
+        .into_iter()
+        .map(|x| match x.to_bigint() {
+            Some(n) => Ok(n),
+            None => Err(MalError::InvalidType(x, "integer".to_string())),
+        })
+        .try_collect()?;
+    Ok(MalVal::from(ints.into_iter().reduce(op).unwrap()))
+}
+
+// シフトする整数とシフト量 (非負の整数)
+fn shift_args(name: &str, args: &[MalVal]) -> Result<(BigInt, u64), MalError> {
+    match args {
+        [x, n] => {
+            let x = x
+                .to_bigint()
+                .ok_or(MalError::InvalidType(x.clone(), "integer".to_string()))?;
+            match n {
+                MalVal::Number(n) if *n >= 0 => Ok((x, *n as u64)),
+                _ => Err(MalError::InvalidType(
+                    n.clone(),
+                    "non-negative integer".to_string(),
+                )),
+            }
+        }
+        _ => Err(MalError::WrongArity(
+            name.to_string(),
+            Arity::Fixed(2),
+            args.len(),
+        )),
+    }
+}
+
+// 数値と0の比較結果がordか. NaNは常にfalse
+fn sign_is(name: &str, args: Vec<MalVal>, ord: Ordering) -> MalResult {
+    match args.as_slice() {
+        [x] if x.to_f64().is_some() => Ok(MalVal::Bool(x.num_cmp(&MalVal::Number(0)) == Some(ord))),
+        [x] => Err(MalError::InvalidType(x.clone(), "number".to_string())),
+        _ => Err(MalError::WrongArity(
+            name.to_string(),
+            Arity::Fixed(1),
+            args.len(),
+        )),
+    }
+}
+
+fn is_odd(name: &str, args: Vec<MalVal>) -> Result<bool, MalError> {
+    match args.as_slice() {
+        [x] => x
+            .to_bigint()
+            .map(|n| n.bit(0))
+            .ok_or(MalError::InvalidType(x.clone(), "integer".to_string())),
+        _ => Err(MalError::WrongArity(
+            name.to_string(),
+            Arity::Fixed(1),
+            args.len(),
+        )),
+    }
+}
+
+// min-keyとmax-key: (k x)が最もkeepの側にあるx. 同じ場合は後のものを返す
+// 比較関数がなければcompareと同じく値で比較する
+fn sort_compare(comparator: Option<&MalVal>, a: &MalVal, b: &MalVal) -> Result<Ordering, MalError> {
+    match comparator {
+        Some(_) => sorted::compare_by(comparator, a, b),
+        None => Ok(a.value_cmp(b)),
+    }
+}
+
+fn extreme_key(name: &str, args: Vec<MalVal>, keep: Ordering) -> MalResult {
+    let len = args.len();
+    let mut args = args.into_iter();
+    let (Some(k), Some(first)) = (args.next(), args.next()) else {
+        return Err(MalError::WrongArity(
+            name.to_string(),
+            Arity::Variadic(2),
+            len,
+        ));
+    };
+    let init = (k.apply(vec![first.clone()])?, first);
+    args.try_fold(init, |(best_key, best), x| {
+        let key = k.apply(vec![x.clone()])?;
+        Ok(if key.value_cmp(&best_key) == keep.reverse() {
+            (best_key, best)
+        } else {
+            (key, x)
+        })
+    })
+    .map(|(_, x)| x)
+}
+
+fn as_str(x: &MalVal) -> Result<&str, MalError> {
+    match x {
+        MalVal::String(s) => Ok(s),
+        _ => Err(MalError::InvalidType(x.clone(), "string".to_string())),
+    }
+}
+
+// N個の文字列を受け取る関数の引数
+fn str_args<'a, const N: usize>(name: &str, args: &'a [MalVal]) -> Result<[&'a str; N], MalError> {
+    if args.len() != N {
+        return Err(MalError::WrongArity(
+            name.to_string(),
+            Arity::Fixed(N),
+            args.len(),
+        ));
+    }
+    let mut strs = [""; N];
+    for (s, x) in strs.iter_mut().zip(args) {
+        *s = as_str(x)?;
+    }
+    Ok(strs)
+}
+
+// 文字列を一つ受け取り、文字列を返す関数
+fn str_map(name: &str, args: Vec<MalVal>, f: fn(&str) -> String) -> MalResult {
+    let [s] = str_args(name, &args)?;
+    Ok(MalVal::string(f(s)))
+}
+
+// 文字列を二つ受け取る述語
+fn str_test(name: &str, args: Vec<MalVal>, f: fn(&str, &str) -> bool) -> MalResult {
+    let [s, sub] = str_args(name, &args)?;
+    Ok(MalVal::Bool(f(s, sub)))
+}
+
+// 文字単位の添字をバイト単位の位置に変換する. 末尾の次までが有効で、それより後はNone
+fn byte_offset(s: &str, index: usize) -> Option<usize> {
+    s.char_indices().map(|(i, _)| i).chain([s.len()]).nth(index)
+}
+
+// 正規表現と文字列を受け取る関数の引数
+fn regex_args<'a>(name: &str, args: &'a [MalVal]) -> Result<(&'a Regex, &'a str), MalError> {
+    match args {
+        [MalVal::Regex(re), s] => Ok((re, as_str(s)?)),
+        [re, _] => Err(MalError::InvalidType(re.clone(), "regex".to_string())),
+        _ => Err(MalError::WrongArity(
+            name.to_string(),
+            Arity::Fixed(2),
+            args.len(),
+        )),
+    }
+}
+
+// マッチした文字列. グループがあれば [全体 グループ1 ...] のベクタで、マッチしなかったグループはnil
+fn match_groups(caps: &Captures) -> MalVal {
+    if caps.len() == 1 {
+        MalVal::string(&caps[0])
+    } else {
+        group_vec(caps)
+    }
+}
+
+fn group_vec(caps: &Captures) -> MalVal {
+    MalVal::vec(
+        caps.iter()
+            .map(|m| m.map_or(MalVal::Nil, |m| MalVal::string(m.as_str())))
+            .collect(),
+    )
+}
+
+fn fast_print<I>(mut s: I)
+where
+    I: Iterator<Item = String>,
+{
+    let mut out = BufWriter::new(io::stdout().lock());
+    if let Some(str) = s.next() {
+        out.write_all(str.as_bytes()).unwrap();
+        s.for_each(|str| {
+            out.write_all(b" ").unwrap();
+            out.write_all(str.as_bytes()).unwrap();
+        });
+    }
+    out.write_all(b"\n").unwrap();
+}
+
+// マップにconjする要素を(キー, 値)の組にする: 要素は[k v]のベクタかマップ
+fn map_entries(x: MalVal) -> Result<Vec<(MalVal, MalVal)>, MalError> {
+    match (&x, x.map_iter()) {
+        (MalVal::Vector(entry, _), _) if entry.len() == 2 => {
+            Ok(vec![(entry[0].clone(), entry[1].clone())])
+        }
+        (_, Some(m)) => Ok(m.map(|(k, v)| (k.clone(), v.clone())).collect()),
+        _ => Err(MalError::InvalidType(
+            x.clone(),
+            "map entry or map".to_string(),
+        )),
+    }
+}
+
+// kvsはキーと値を交互に並べたもの
+fn assoc(coll: MalVal, kvs: Vec<MalVal>) -> MalResult {
+    match coll {
+        MalVal::Nil => Ok(MalVal::hashmap(kvs.into_iter().tuples().collect())),
+        MalVal::HashMap(m, meta) => {
+            let mut m = Rc::unwrap_or_clone(m);
+            m.extend(kvs.into_iter().tuples());
+            Ok(MalVal::HashMap(Rc::new(m), meta))
+        }
+        MalVal::SortedMap(m, meta) => {
+            let mut m = Rc::unwrap_or_clone(m);
+            kvs.into_iter().tuples().try_for_each(|kv| m.insert(kv))?;
+            Ok(MalVal::SortedMap(Rc::new(m), meta))
+        }
+        MalVal::Vector(v, meta) => {
+            let mut v = Rc::unwrap_or_clone(v);
+            kvs.into_iter()
+                .tuples()
+                .try_for_each(|(k, x)| assoc_vec(&mut v, k, x))?;
+            Ok(MalVal::Vector(Rc::new(v), meta))
+        }
+        z => Err(MalError::InvalidType(z, "nil, map or vector".to_string())),
+    }
+}
+
+fn dissoc(coll: MalVal, ks: &[MalVal]) -> MalResult {
+    match coll {
+        MalVal::Nil => Ok(MalVal::Nil),
+        MalVal::HashMap(m, meta) => {
+            let mut m = Rc::unwrap_or_clone(m);
+            ks.iter().for_each(|k| {
+                m.remove(k);
+            });
+            Ok(MalVal::HashMap(Rc::new(m), meta))
+        }
+        MalVal::SortedMap(m, meta) => {
+            let mut m = Rc::unwrap_or_clone(m);
+            ks.iter().try_for_each(|k| m.remove(k))?;
+            Ok(MalVal::SortedMap(Rc::new(m), meta))
+        }
+        z => Err(MalError::InvalidType(z, "nil or map".to_string())),
+    }
+}
+
+// パスの途中のコレクションにfを適用した結果で置き換える. 途中が無ければnilから作る
+fn update_in(coll: MalVal, path: &[MalVal], f: &mut dyn FnMut(MalVal) -> MalResult) -> MalResult {
+    match path {
+        [] => f(coll),
+        [k, ks @ ..] => {
+            let inner = coll.get(k)?.unwrap_or(MalVal::Nil);
+            let inner = update_in(inner, ks, f)?;
+            assoc(coll, vec![k.clone(), inner])
+        }
+    }
+}
+
+// パスのキーを取り除く. ベクタは要素を詰める. 途中が無ければそのまま返す
+fn dissoc_in(coll: MalVal, path: &[MalVal]) -> MalResult {
+    match path {
+        [] => Ok(coll),
+        [k] => match coll {
+            MalVal::Vector(v, meta) => match k {
+                MalVal::Number(i) if (0..v.len() as i64).contains(i) => {
+                    let mut v = Rc::unwrap_or_clone(v);
+                    v.remove(*i as usize);
+                    Ok(MalVal::Vector(Rc::new(v), meta))
+                }
+                _ => Ok(MalVal::Vector(v, meta)),
+            },
+            coll => dissoc(coll, std::slice::from_ref(k)),
+        },
+        [k, ks @ ..] => match coll.get(k)? {
+            Some(inner) => {
+                let inner = dissoc_in(inner, ks)?;
+                assoc(coll, vec![k.clone(), inner])
+            }
+            None => Ok(coll),
+        },
+    }
+}
+
+// マップとベクタを [k v] のベクタとして扱う
+fn map_entry(k: MalVal, v: MalVal) -> MalVal {
+    MalVal::vec(Vector::from_iter([k, v]))
+}
+
+// ベクタは添字に対して値を設定する. 末尾の次の添字なら追加する
+fn assoc_vec(v: &mut Vector<MalVal>, k: MalVal, x: MalVal) -> Result<(), MalError> {
+    match k {
+        MalVal::Number(i) if (0..v.len() as i64).contains(&i) => {
+            v.set(i as usize, x);
+        }
+        MalVal::Number(i) if i == v.len() as i64 => v.push_back(x),
+        MalVal::Number(i) => return Err(MalError::IndexOutOfBounds(i, v.len())),
+        z => return Err(MalError::InvalidType(z, "number".to_string())),
+    }
+    Ok(())
+}
+
+// sorted-map, sorted-map-byの引数からマップを作る
+fn sorted_map(name: &str, comparator: Option<MalVal>, kvs: Vec<MalVal>) -> MalResult {
+    let mut m = Sorted::new(comparator);
+    kv_pairs(name, kvs)?.try_for_each(|kv| m.insert(kv))?;
+    Ok(MalVal::sorted_map(m))
+}
+
+// キーと値を交互に並べた引数を組にする. 奇数個ならエラー
+fn kv_pairs(
+    name: &str,
+    kvs: Vec<MalVal>,
+) -> Result<impl Iterator<Item = (MalVal, MalVal)>, MalError> {
+    if kvs.len() % 2 == 1 {
+        return Err(MalError::InvalidSyntax(format!(
+            "{} requires an even number of key/value arguments",
+            name
+        )));
+    }
+    Ok(kvs.into_iter().tuples())
+}
+
+// (subseq sc test key) または (subseq sc start-test start-key end-test end-key)
+fn subseq(name: &str, args: &[MalVal]) -> Result<Vec<MalVal>, MalError> {
+    let (coll, bounds) = match args {
+        [coll, bounds @ ..] if matches!(bounds.len(), 2 | 4) => (coll, bounds),
+        _ => {
+            return Err(MalError::WrongArity(
+                name.to_string(),
+                Arity::Either(3, 5),
+                args.len(),
+            ))
+        }
+    };
+    let bounds = bounds.iter().cloned().tuples().collect_vec();
+    match coll {
+        MalVal::SortedMap(m, _) => Ok(m.subseq(&bounds)?.iter().map(Entry::to_mal).collect()),
+        MalVal::SortedSet(s, _) => Ok(s.subseq(&bounds)?.iter().map(Entry::to_mal).collect()),
+        z => Err(MalError::InvalidType(
+            z.clone(),
+            "sorted-map or sorted-set".to_string(),
+        )),
+    }
+}
+
+// 浮動小数点数を、その10進表記が表す分数にする (0.1 => 1/10)
+fn rationalize(f: f64) -> Option<BigRational> {
+    if !f.is_finite() {
+        return None;
+    }
+    // {:e}は仮数部が最短の10進表記になる: 1.5e-7
+    let repr = format!("{:e}", f);
+    let (mantissa, exp) = repr.split_once('e')?;
+    let exp: i32 = exp.parse().ok()?;
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits: BigInt = format!("{}{}", int, frac).parse().ok()?;
+    let exp = exp - frac.len() as i32;
+    let scale = BigInt::from(10).pow(exp.unsigned_abs());
+    Some(if exp >= 0 {
+        BigRational::from_integer(digits * scale)
+    } else {
+        BigRational::new(digits, scale)
+    })
+}
+
+// シーケンスとして要素を順に返す. 扱えない値はエラー
+fn seq_of(coll: &MalVal) -> Result<Seq<'_>, MalError> {
+    coll.seq()
+        .ok_or(MalError::InvalidType(coll.clone(), SEQABLE.to_string()))
+}
+
+fn seq_items(coll: &MalVal) -> Result<Vec<MalVal>, MalError> {
+    seq_of(coll)?.try_collect()
+}
+
+fn count_of(coll: &MalVal) -> Result<usize, MalError> {
+    match coll.count() {
+        Some(n) => Ok(n),
+        // 遅延シーケンスは最後まで計算して数える
+        None => seq_of(coll)?.try_fold(0, |n, x| x.map(|_| n + 1)),
+    }
+}
+
+// 空ならnil. リストと遅延シーケンスはそのまま、それ以外はリストにする
+fn seq(coll: &MalVal) -> MalResult {
+    match coll {
+        MalVal::List(l, _) if l.is_empty() => Ok(MalVal::Nil),
+        MalVal::List(_, _) => Ok(coll.clone()),
+        MalVal::LazySeq(_) if coll.uncons()?.is_none() => Ok(MalVal::Nil),
+        MalVal::LazySeq(_) => Ok(coll.clone()),
+        _ => {
+            let xs = seq_items(coll)?;
+            Ok(if xs.is_empty() {
+                MalVal::Nil
+            } else {
+                MalVal::list(xs)
+            })
+        }
+    }
+}
+
+// 先頭を除いた要素. リストと遅延シーケンスは末尾を共有する
+fn rest(coll: &MalVal) -> MalResult {
+    match coll.uncons()? {
+        Some((_, rest @ (MalVal::List(_, _) | MalVal::LazySeq(_)))) => Ok(rest),
+        Some((_, rest)) => Ok(MalVal::list(seq_items(&rest)?)),
+        None => Ok(MalVal::list(vec![])),
+    }
+}
+
+fn truthy(x: &MalVal) -> bool {
+    !matches!(x, MalVal::Nil | MalVal::Bool(false))
+}
+
+// 組み込み関数が作る遅延シーケンス
+fn lazy<F>(f: F) -> MalVal
+where
+    F: FnOnce() -> Result<Step, MalError> + 'static,
+{
+    MalVal::LazySeq(Rc::new(LazySeq::native(f)))
+}
+
+fn take(n: usize, coll: MalVal) -> MalVal {
+    lazy(move || match n {
+        0 => Ok(None),
+        _ => Ok(coll.uncons()?.map(|(x, rest)| (x, take(n - 1, rest)))),
+    })
+}
+
+// 複数のコレクションは最も短いものに合わせて並べて適用する
+fn map(f: MalVal, colls: Vec<MalVal>) -> MalVal {
+    lazy(move || {
+        let mut xs = vec![];
+        let mut rests = vec![];
+        for coll in colls {
+            let Some((x, rest)) = coll.uncons()? else {
+                return Ok(None);
+            };
+            xs.push(x);
+            rests.push(rest);
+        }
+        Ok(Some((f.apply(xs)?, map(f, rests))))
+    })
+}
+
+fn drop(n: usize, coll: MalVal) -> MalVal {
+    lazy(move || {
+        let mut coll = coll;
+        for _ in 0..n {
+            match coll.uncons()? {
+                Some((_, rest)) => coll = rest,
+                None => return Ok(None),
+            }
+        }
+        coll.uncons()
+    })
+}
+
+// predの結果の真偽がkeepと一致する要素を残す
+fn filter(pred: MalVal, coll: MalVal, keep: bool) -> MalVal {
+    lazy(move || {
+        let mut coll = coll;
+        while let Some((x, rest)) = coll.uncons()? {
+            if truthy(&pred.apply(vec![x.clone()])?) == keep {
+                return Ok(Some((x, filter(pred, rest, keep))));
+            }
+            coll = rest;
+        }
+        Ok(None)
+    })
+}
+
+// stepが正ならend未満、負ならendより大きい間続く. stepが0なら無限にstartを繰り返す
+fn range(start: MalVal, end: Option<MalVal>, step: MalVal) -> MalVal {
+    lazy(move || {
+        if let Some(end) = &end {
+            let done = match step.num_cmp(&MalVal::Number(0)) {
+                Some(Ordering::Greater) => start.num_cmp(end) != Some(Ordering::Less),
+                Some(Ordering::Less) => start.num_cmp(end) != Some(Ordering::Greater),
+                _ => false,
+            };
+            if done {
+                return Ok(None);
+            }
+        }
+        let next = num_op(
+            start.clone(),
+            step.clone(),
+            i64::checked_add,
+            |a, b| Ok(a + b),
+            |a, b| a + b,
+        )?;
+        Ok(Some((start, range(next, end, step))))
+    })
+}
+
+fn iterate(f: MalVal, x: MalVal) -> MalVal {
+    let next = x.clone();
+    let rest = lazy(move || {
+        let y = f.apply(vec![next])?;
+        iterate(f, y).uncons()
+    });
+    MalVal::LazySeq(Rc::new(LazySeq::cons(x, rest)))
+}
+
+fn repeat(x: MalVal, n: Option<usize>) -> MalVal {
+    lazy(move || match n {
+        Some(0) => Ok(None),
+        _ => Ok(Some((x.clone(), repeat(x, n.map(|n| n - 1))))),
+    })
+}
+
+// restを使い切ったらcollの先頭に戻る. collが空なら空のシーケンス
+fn cycle(coll: MalVal, rest: MalVal) -> MalVal {
+    lazy(move || {
+        let step = match rest.uncons()? {
+            None => coll.uncons()?,
+            step => step,
+        };
+        Ok(step.map(|(x, rest)| (x, cycle(coll, rest))))
+    })
+}
+
+fn keep(f: MalVal, coll: MalVal) -> MalVal {
+    lazy(move || {
+        let mut coll = coll;
+        while let Some((x, rest)) = coll.uncons()? {
+            match f.apply(vec![x])? {
+                MalVal::Nil => coll = rest,
+                y => return Ok(Some((y, keep(f, rest)))),
+            }
+        }
+        Ok(None)
+    })
+}
+
+// collsの各要素のシーケンスを繋げる. curは現在取り出している要素
+fn concat(colls: MalVal, cur: MalVal) -> MalVal {
+    lazy(move || {
+        let (mut colls, mut cur) = (colls, cur);
+        loop {
+            if let Some((x, rest)) = cur.uncons()? {
+                return Ok(Some((x, concat(colls, rest))));
+            }
+            match colls.uncons()? {
+                Some((coll, rest)) => (cur, colls) = (coll, rest),
+                None => return Ok(None),
+            }
+        }
+    })
+}
+
+fn partition(n: usize, step: usize, pad: Option<MalVal>, coll: MalVal) -> MalVal {
+    lazy(move || {
+        let mut chunk = seq_items(&take(n, coll.clone()))?;
+        if chunk.len() == n {
+            let rest = partition(n, step, pad, drop(step, coll));
+            return Ok(Some((MalVal::list(chunk), rest)));
+        }
+        match pad {
+            Some(pad) if !chunk.is_empty() => {
+                chunk.extend(seq_items(&take(n - chunk.len(), pad))?);
+                Ok(Some((MalVal::list(chunk), MalVal::list(vec![]))))
+            }
+            _ => Ok(None),
+        }
+    })
+}
+
+fn partition_by(f: MalVal, coll: MalVal) -> MalVal {
+    lazy(move || {
+        let Some((x, mut rest)) = coll.uncons()? else {
+            return Ok(None);
+        };
+        let key = f.apply(vec![x.clone()])?;
+        let mut run = vec![x];
+        while let Some((y, next)) = rest.uncons()? {
+            if f.apply(vec![y.clone()])? != key {
+                break;
+            }
+            run.push(y);
+            rest = next;
+        }
+        Ok(Some((MalVal::list(run), partition_by(f, rest))))
+    })
+}
+
+fn distinct(coll: MalVal, seen: HashSet<MalVal, FnvBuildHasher>) -> MalVal {
+    lazy(move || {
+        let (mut coll, mut seen) = (coll, seen);
+        while let Some((x, rest)) = coll.uncons()? {
+            if seen.insert(x.clone()).is_none() {
+                return Ok(Some((x, distinct(rest, seen))));
+            }
+            coll = rest;
+        }
+        Ok(None)
+    })
+}
+
+// accに (f acc args...) を順に適用する. (reduced x) が返されたらxを結果として打ち切る
+fn reduce<I>(f: &MalVal, init: MalVal, args: I) -> MalResult
+where
+    I: Iterator<Item = Result<Vec<MalVal>, MalError>>,
+{
+    let mut acc = init;
+    for args in args {
+        acc = f.apply(std::iter::once(acc).chain(args?).collect())?;
+        if let MalVal::Reduced(x) = acc {
+            return Ok(Rc::unwrap_or_clone(x));
+        }
+    }
+    Ok(acc)
+}
+
+fn identity(args: Vec<MalVal>) -> MalResult {
+    match <[MalVal; 1]>::try_from(args) {
+        Ok([x]) => Ok(x),
+        Err(args) => Err(MalError::WrongArity(
+            "identity".to_string(),
+            Arity::Fixed(1),
+            args.len(),
+        )),
+    }
+}
+
+fn comp(fs: Vec<MalVal>) -> MalVal {
+    MalVal::native("comp", move |args| {
+        let mut fs = fs.iter().rev();
+        // SAFETY: 関数は2つ以上ある
+        let x = fs.next().unwrap().apply(args)?;
+        fs.try_fold(x, |x, f| f.apply(vec![x]))
+    })
+}
+
+// transducerは下流の還元関数rfを受け取り、新しい還元関数を返す関数
+// 還元関数は () で初期値、(acc) で完了時の処理、(acc x) で要素ごとの処理を行う
+// makeはrfごとに呼んで還元関数の本体を作る. 状態を持つ場合はその中に閉じ込める
+fn transducer<F, R>(name: &'static str, make: F) -> MalVal
+where
+    F: Fn(MalVal) -> R + 'static,
+    R: Fn(Reducing) -> MalResult + 'static,
+{
+    MalVal::native(name, move |args| {
+        let [rf] = <[MalVal; 1]>::try_from(args)
+            .map_err(|args| MalError::WrongArity(name.to_string(), Arity::Fixed(1), args.len()))?;
+        let step = make(rf);
+        Ok(MalVal::native(name, move |args| {
+            step(Reducing::new(name, args)?)
+        }))
+    })
+}
+
+// 還元関数の呼び出し方
+enum Reducing {
+    Init,
+    Complete(MalVal),
+    Step(MalVal, MalVal),
+}
+
+impl Reducing {
+    fn new(name: &str, args: Vec<MalVal>) -> Result<Self, MalError> {
+        match <[MalVal; 2]>::try_from(args) {
+            Ok([acc, x]) => Ok(Reducing::Step(acc, x)),
+            Err(mut args) => match args.len() {
+                0 => Ok(Reducing::Init),
+                1 => Ok(Reducing::Complete(args.pop().unwrap())),
+                n => Err(MalError::WrongArity(
+                    name.to_string(),
+                    Arity::JustOrOneLess(2),
+                    n,
+                )),
+            },
+        }
+    }
+
+    // 初期値と完了時の処理は下流にそのまま任せる
+    fn forward(self, rf: &MalVal) -> MalResult {
+        match self {
+            Reducing::Init => rf.apply(vec![]),
+            Reducing::Complete(acc) => rf.apply(vec![acc]),
+            Reducing::Step(acc, x) => rf.apply(vec![acc, x]),
+        }
+    }
+}
+
+fn map_xf(f: MalVal) -> MalVal {
+    transducer("map", move |rf| {
+        let f = f.clone();
+        move |call| match call {
+            Reducing::Step(acc, x) => rf.apply(vec![acc, f.apply(vec![x])?]),
+            call => call.forward(&rf),
+        }
+    })
+}
+
+fn filter_xf(pred: MalVal) -> MalVal {
+    transducer("filter", move |rf| {
+        let pred = pred.clone();
+        move |call| match call {
+            Reducing::Step(acc, x) if !truthy(&pred.apply(vec![x.clone()])?) => Ok(acc),
+            call => call.forward(&rf),
+        }
+    })
+}
+
+// 状態は残りの要素数
+fn take_xf(n: i64) -> MalVal {
+    transducer("take", move |rf| {
+        let remaining = Cell::new(n);
+        move |call| match call {
+            Reducing::Step(acc, x) => {
+                let m = remaining.replace(remaining.get() - 1);
+                let acc = if m > 0 { rf.apply(vec![acc, x])? } else { acc };
+                // 取り終えたらそれ以上の要素を読まない
+                Ok(match acc {
+                    MalVal::Reduced(_) => acc,
+                    _ if m <= 1 => MalVal::Reduced(Rc::new(acc)),
+                    _ => acc,
+                })
+            }
+            call => call.forward(&rf),
+        }
+    })
+}
+
+// 状態はたまった要素
+fn partition_all_xf(n: usize) -> MalVal {
+    transducer("partition-all", move |rf| {
+        let buf = RefCell::new(Vector::new());
+        move |call| {
+            let flush = |acc| {
+                let xs = buf.take();
+                if xs.is_empty() {
+                    Ok(acc)
+                } else {
+                    rf.apply(vec![acc, MalVal::vec(xs)])
+                }
+            };
+            match call {
+                Reducing::Step(acc, x) => {
+                    buf.borrow_mut().push_back(x);
+                    if buf.borrow().len() >= n {
+                        flush(acc)
+                    } else {
+                        Ok(acc)
+                    }
+                }
+                // 残りを出してから完了する
+                Reducing::Complete(acc) => {
+                    let acc = match flush(acc)? {
+                        MalVal::Reduced(x) => Rc::unwrap_or_clone(x),
+                        acc => acc,
+                    };
+                    rf.apply(vec![acc])
+                }
+                call => call.forward(&rf),
+            }
+        }
+    })
+}
+
+// xformを通してfで畳み込み、最後に (f acc) で完了させる
+fn transduce(xform: &MalVal, f: MalVal, init: MalVal, coll: &MalVal) -> MalResult {
+    let rf = xform.apply(vec![f])?;
+    let acc = reduce(&rf, init, seq_of(coll)?.map(|x| x.map(|x| vec![x])))?;
+    rf.apply(vec![acc])
+}
+
+// xformを通した要素を必要になった分だけ計算する遅延シーケンス
+fn eduction(xform: &MalVal, coll: MalVal) -> MalResult {
+    // 下流の還元関数は出力された要素をbufにためる
+    let buf = Rc::new(RefCell::new(Vector::new()));
+    let out = buf.clone();
+    let push = MalVal::native("eduction", move |mut args| {
+        if let Some(x) = args.get(1) {
+            out.borrow_mut().push_back(x.clone());
+        }
+        Ok(args.swap_remove(0))
+    });
+    Ok(educe(xform.apply(vec![push])?, buf, Some(coll)))
+}
+
+// collがNoneなら入力は終わっていて、bufに残った要素だけを返す
+fn educe(rf: MalVal, buf: Rc<RefCell<Vector<MalVal>>>, coll: Option<MalVal>) -> MalVal {
+    lazy(move || {
+        let mut coll = coll;
+        loop {
+            let xs = buf.take();
+            if !xs.is_empty() {
+                let rest = educe(rf, buf.clone(), coll);
+                let mut xs = xs.into_iter().rev();
+                let last = xs.next().unwrap();
+                let rest = xs.fold(
+                    MalVal::LazySeq(Rc::new(LazySeq::cons(last, rest))),
+                    |rest, x| MalVal::LazySeq(Rc::new(LazySeq::cons(x, rest))),
+                );
+                return rest.uncons();
+            }
+            let Some(c) = coll else {
+                return Ok(None);
+            };
+            coll = match c.uncons()? {
+                Some((x, rest)) => match rf.apply(vec![MalVal::Nil, x])? {
+                    MalVal::Reduced(_) => {
+                        rf.apply(vec![MalVal::Nil])?;
+                        None
+                    }
+                    _ => Some(rest),
+                },
+                None => {
+                    rf.apply(vec![MalVal::Nil])?;
+                    None
+                }
+            };
+        }
+    })
+}
+
+fn take_while(pred: MalVal, coll: MalVal) -> MalVal {
+    lazy(move || match coll.uncons()? {
+        Some((x, rest)) if truthy(&pred.apply(vec![x.clone()])?) => {
+            Ok(Some((x, take_while(pred, rest))))
+        }
+        _ => Ok(None),
+    })
+}
+
+// takeとdropの引数: 負の数は0とみなす
+fn count_args<'a>(name: &str, args: &'a [MalVal]) -> Result<(usize, &'a MalVal), MalError> {
+    match args {
+        [MalVal::Number(n), coll] => Ok(((*n).max(0) as usize, coll)),
+        [n, _] => Err(MalError::InvalidType(n.clone(), "integer".to_string())),
+        _ => Err(MalError::WrongArity(
+            name.to_string(),
+            Arity::Fixed(2),
+            args.len(),
+        )),
+    }
+}
+
+// コレクションに要素を追加する. リストは先頭に、それ以外は末尾に追加する
+fn conj(coll: MalVal, xs: Vec<MalVal>) -> MalResult {
+    match coll {
+        MalVal::Nil => Ok(MalVal::list(xs.into_iter().rev().collect())),
+        // リストは先頭に追加する
+        MalVal::List(l, meta) => Ok(MalVal::List(xs.into_iter().fold(l, |l, x| l.cons(x)), meta)),
+        MalVal::Vector(v, meta) => {
+            let mut v = Rc::unwrap_or_clone(v);
+            v.extend(xs);
+            Ok(MalVal::Vector(Rc::new(v), meta))
+        }
+        MalVal::HashMap(m, meta) => {
+            let mut m = Rc::unwrap_or_clone(m);
+            for x in xs {
+                m.extend(map_entries(x)?);
+            }
+            Ok(MalVal::HashMap(Rc::new(m), meta))
+        }
+        MalVal::SortedMap(m, meta) => {
+            let mut m = Rc::unwrap_or_clone(m);
+            for x in xs {
+                map_entries(x)?
+                    .into_iter()
+                    .try_for_each(|entry| m.insert(entry))?;
+            }
+            Ok(MalVal::SortedMap(Rc::new(m), meta))
+        }
+        MalVal::HashSet(set, meta) => {
+            let mut set = Rc::unwrap_or_clone(set);
+            set.extend(xs);
+            Ok(MalVal::HashSet(Rc::new(set), meta))
+        }
+        MalVal::SortedSet(set, meta) => {
+            let mut set = Rc::unwrap_or_clone(set);
+            xs.into_iter().try_for_each(|x| set.insert(x))?;
+            Ok(MalVal::SortedSet(Rc::new(set), meta))
+        }
+        z => Err(MalError::InvalidType(
+            z,
+            "nil, list, vector, map or set".to_string(),
+        )),
+    }
+}
+
+// 集合演算の引数を集合として取り出す. nilは空集合として扱う
+fn into_sets(args: Vec<MalVal>) -> Result<Vec<HashSet<MalVal, FnvBuildHasher>>, MalError> {
+    args.into_iter()
+        .map(|x| match x {
+            MalVal::Nil => Ok(HashSet::default()),
+            MalVal::HashSet(set, _) => Ok(Rc::unwrap_or_clone(set)),
+            MalVal::SortedSet(set, _) => Ok(set.iter().cloned().collect()),
+            z => Err(MalError::InvalidType(z, "set".to_string())),
+        })
+        .collect()
+}
+
+// transientの中身をその場で書き換えて、transient自身を返す
+fn edit_transient<F>(name: &str, arity: Arity, args: Vec<MalVal>, edit: F) -> MalResult
+where
+    F: FnOnce(&mut MalVal, Vec<MalVal>) -> Result<(), MalError>,
+{
+    // キーのハッシュの計算で遅延シーケンスが同じtransientを使っても二重に借用しないように、
+    // 借用する前に計算しておく. 計算済みの遅延シーケンスは再び評価されない
+    args.iter()
+        .skip(1)
+        .for_each(|x| x.hash(&mut DefaultHasher::new()));
+    let mut args = args.into_iter();
+    match args.next() {
+        Some(MalVal::Transient(t)) => {
+            match t.borrow_mut().as_mut() {
+                Some(coll) => edit(coll, args.collect())?,
+                None => return Err(MalError::PersistedTransient),
+            }
+            Ok(MalVal::Transient(t))
+        }
+        Some(z) => Err(MalError::InvalidType(z, "transient".to_string())),
+        None => Err(MalError::WrongArity(name.to_string(), arity, 0)),
+    }
+}
+
+// transientは中身のコレクションを読む. それ以外の値はそのまま読む
+fn read_transient<T, F>(coll: &MalVal, read: F) -> Result<T, MalError>
+where
+    F: FnOnce(&MalVal) -> Result<T, MalError>,
+{
+    match coll {
+        MalVal::Transient(t) => match t.borrow().as_ref() {
+            Some(coll) => read(coll),
+            None => Err(MalError::PersistedTransient),
+        },
+        _ => read(coll),
+    }
+}
+
+// transientの中身のRcは他から参照されないので、複製せずに書き換えられる
+fn owned<T>(rc: &mut Rc<T>) -> Result<&mut T, MalError> {
+    Rc::get_mut(rc).ok_or(MalError::SharedTransient)
 }
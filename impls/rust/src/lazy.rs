@@ -0,0 +1,128 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::rc::Rc;
+
+use crate::types::{MalError, MalResult, MalVal};
+
+/// 先頭の要素と残りのシーケンス. 空ならNone
+pub type Step = Option<(MalVal, MalVal)>;
+
+/// 組み込み関数が作る遅延シーケンスの中身を計算する関数
+pub type Thunk = Box<dyn FnOnce() -> Result<Step, MalError>>;
+
+enum State {
+    Fn(MalVal),    // (lazy-seq body) で作られた引数なしの関数. シーケンスとして扱える値を返す
+    Native(Thunk), // 組み込み関数が作った計算
+    Pending,       // 計算中
+    Realized(Step),
+    Failed(MalError),
+}
+
+/// 必要になるまで中身を計算しないシーケンス
+/// 一度計算した結果 (エラーを含む) は記憶し、二度目からはそれを返す
+pub struct LazySeq {
+    state: RefCell<State>,
+}
+
+impl LazySeq {
+    pub fn new(f: MalVal) -> Self {
+        LazySeq {
+            state: RefCell::new(State::Fn(f)),
+        }
+    }
+
+    pub fn native<F>(f: F) -> Self
+    where
+        F: FnOnce() -> Result<Step, MalError> + 'static,
+    {
+        LazySeq {
+            state: RefCell::new(State::Native(Box::new(f))),
+        }
+    }
+
+    /// 計算済みの (first . rest). restが遅延シーケンスならそのまま遅延させる
+    pub fn cons(first: MalVal, rest: MalVal) -> Self {
+        LazySeq {
+            state: RefCell::new(State::Realized(Some((first, rest)))),
+        }
+    }
+
+    /// 中身を計算して先頭の要素と残りを返す
+    pub fn step(&self) -> Result<Step, MalError> {
+        let state = self.state.replace(State::Pending);
+        let result = match state {
+            State::Realized(step) => Ok(step),
+            State::Failed(e) => Err(e),
+            // 計算中に自身の中身を必要とした
+            State::Pending => return Err(MalError::LazySeqCycle),
+            State::Fn(f) => f.apply(vec![]).and_then(|seq| seq.uncons()),
+            State::Native(f) => f(),
+        };
+        *self.state.borrow_mut() = match &result {
+            Ok(step) => State::Realized(step.clone()),
+            Err(e) => State::Failed(e.clone()),
+        };
+        result
+    }
+
+    pub fn is_realized(&self) -> bool {
+        matches!(*self.state.borrow(), State::Realized(_))
+    }
+}
+
+// 長い遅延シーケンスで再帰的にdropしてスタックが溢れないようにする
+impl Drop for LazySeq {
+    fn drop(&mut self) {
+        let mut next = match self.state.get_mut() {
+            State::Realized(Some((_, rest))) => std::mem::replace(rest, MalVal::Nil),
+            _ => return,
+        };
+        while let MalVal::LazySeq(seq) = next {
+            match Rc::try_unwrap(seq) {
+                Ok(mut seq) => {
+                    next = match seq.state.get_mut() {
+                        State::Realized(Some((_, rest))) => std::mem::replace(rest, MalVal::Nil),
+                        _ => break,
+                    }
+                }
+                Err(_) => break, // 他から参照されている部分は残す
+            }
+        }
+    }
+}
+
+impl Debug for LazySeq {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "LazySeq(realized: {})", self.is_realized())
+    }
+}
+
+/// シーケンスとして扱える値の要素を先頭から一つずつ計算するイテレータ
+/// 計算に失敗した場合はエラーを返して終わる
+pub struct Iter {
+    rest: MalVal,
+}
+
+impl Iter {
+    pub fn new(seq: MalVal) -> Self {
+        Iter { rest: seq }
+    }
+}
+
+impl Iterator for Iter {
+    type Item = MalResult;
+
+    fn next(&mut self) -> Option<MalResult> {
+        match self.rest.uncons() {
+            Ok(Some((first, rest))) => {
+                self.rest = rest;
+                Some(Ok(first))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                self.rest = MalVal::Nil;
+                Some(Err(e))
+            }
+        }
+    }
+}
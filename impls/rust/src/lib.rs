@@ -2,6 +2,9 @@
 
 pub mod core;
 pub mod env;
+pub mod lazy;
+pub mod list;
 pub mod printer;
 pub mod reader;
+pub mod sorted;
 pub mod types;
@@ -0,0 +1,135 @@
+use std::iter::FusedIterator;
+use std::rc::Rc;
+
+use crate::types::MalVal;
+
+#[derive(Debug)]
+struct Node {
+    value: MalVal,
+    next: Option<Rc<Node>>,
+}
+
+/// 末尾を共有する単方向連結リスト
+/// consとrestは要素をコピーせずO(1)で新しいリストを作る
+#[derive(Debug, Clone, Default)]
+pub struct List {
+    head: Option<Rc<Node>>,
+    len: usize,
+}
+
+impl List {
+    pub fn new() -> Self {
+        List::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 先頭に要素を追加したリストを返す
+    pub fn cons(&self, value: MalVal) -> Self {
+        List {
+            head: Some(Rc::new(Node {
+                value,
+                next: self.head.clone(),
+            })),
+            len: self.len + 1,
+        }
+    }
+
+    pub fn first(&self) -> Option<&MalVal> {
+        self.head.as_ref().map(|node| &node.value)
+    }
+
+    /// 先頭を除いたリストを返す. 空リストの場合は空リスト
+    pub fn rest(&self) -> Self {
+        match &self.head {
+            Some(node) => List {
+                head: node.next.clone(),
+                len: self.len - 1,
+            },
+            None => List::new(),
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&MalVal> {
+        self.iter().nth(index)
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            next: self.head.as_deref(),
+            len: self.len,
+        }
+    }
+}
+
+// 長いリストで再帰的にdropしてスタックが溢れないようにする
+impl Drop for List {
+    fn drop(&mut self) {
+        let mut next = self.head.take();
+        while let Some(node) = next {
+            match Rc::try_unwrap(node) {
+                Ok(mut node) => next = node.next.take(),
+                Err(_) => break, // 他のリストと共有している部分は残す
+            }
+        }
+    }
+}
+
+impl FromIterator<MalVal> for List {
+    fn from_iter<I: IntoIterator<Item = MalVal>>(iter: I) -> Self {
+        iter.into_iter()
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .fold(List::new(), |list, value| list.cons(value))
+    }
+}
+
+impl From<Vec<MalVal>> for List {
+    fn from(vec: Vec<MalVal>) -> Self {
+        vec.into_iter()
+            .rev()
+            .fold(List::new(), |list, value| list.cons(value))
+    }
+}
+
+impl<'a> IntoIterator for &'a List {
+    type Item = &'a MalVal;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Iter<'a> {
+    next: Option<&'a Node>,
+    len: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a MalVal;
+
+    fn next(&mut self) -> Option<&'a MalVal> {
+        self.next.map(|node| {
+            self.next = node.next.as_deref();
+            self.len -= 1;
+            &node.value
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl FusedIterator for Iter<'_> {}
@@ -1,11 +1,77 @@
-use crate::types::MalVal;
+use crate::types::{Arity, MalError, MalVal};
 use itertools::Itertools;
+use std::cell::Cell;
+use std::iter::Peekable;
+use std::str::Chars;
+
+thread_local! {
+    // 遅延シーケンスを表示する最大の要素数. Noneなら制限しない (デフォルト)
+    static PRINT_LENGTH: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+// 制限がない場合にREPLの結果の表示に用いる最大の要素数. pr-strやstrには適用しない
+const REPL_PRINT_LENGTH: usize = 100;
+
+pub fn set_print_length(n: Option<usize>) {
+    PRINT_LENGTH.set(n);
+}
+
+fn print_length() -> usize {
+    PRINT_LENGTH.get().unwrap_or(usize::MAX)
+}
+
+/// 表示する範囲の遅延シーケンスを計算してからpr_strする. 計算に失敗した場合はそのエラーを返す
+pub fn try_pr_str(form: &MalVal, print_readably: bool) -> Result<String, MalError> {
+    realize(form)?;
+    Ok(pr_str(form, print_readably))
+}
+
+/// REPLの結果を表示する. 制限がなくても無限の遅延シーケンスを表示できるように、
+/// REPL_PRINT_LENGTH個までに制限する
+pub fn repl_pr_str(form: &MalVal) -> Result<String, MalError> {
+    let n = PRINT_LENGTH.get();
+    PRINT_LENGTH.set(n.or(Some(REPL_PRINT_LENGTH)));
+    let result = try_pr_str(form, true);
+    PRINT_LENGTH.set(n);
+    result
+}
+
+// 入れ子になったコレクションも含めて、表示する範囲の遅延シーケンスを計算する
+fn realize(form: &MalVal) -> Result<(), MalError> {
+    match form {
+        MalVal::LazySeq(_) => {
+            let mut xs = form.seq().unwrap();
+            for x in xs.by_ref().take(print_length()) {
+                realize(&x?)?;
+            }
+            // 続きがあるかどうかの判定に使う
+            xs.next().transpose()?;
+            Ok(())
+        }
+        MalVal::HashMap(_, _) | MalVal::SortedMap(_, _) => form
+            .map_iter()
+            .unwrap()
+            .try_for_each(|(k, v)| realize(k).and_then(|_| realize(v))),
+        _ => {
+            if let Some(mut xs) = form.seq_iter() {
+                xs.try_for_each(realize)
+            } else if let Some(mut xs) = form.set_iter() {
+                xs.try_for_each(realize)
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
 
 pub fn pr_str(form: &MalVal, print_readably: bool) -> String {
     match form {
         MalVal::Nil => String::from("nil"),
         MalVal::Bool(b) => b.to_string(),
         MalVal::Number(n) => n.to_string(),
+        MalVal::BigInt(n) => n.to_string(),
+        MalVal::Float(f) => print_float(*f),
+        MalVal::Ratio(r) => format!("{}/{}", r.numer(), r.denom()),
         MalVal::String(s) => {
             if print_readably {
                 format!("\"{}\"", escape(s))
@@ -15,6 +81,13 @@ pub fn pr_str(form: &MalVal, print_readably: bool) -> String {
         }
         MalVal::Keyword(k) => format!(":{}", k),
         MalVal::Symbol(s) => s.to_string(),
+        MalVal::Regex(re) => {
+            if print_readably {
+                format!("#\"{}\"", escape_regex(re.as_str()))
+            } else {
+                re.as_str().to_string()
+            }
+        }
         MalVal::List(l, _) => format!(
             "({})",
             l.iter().map(|e| pr_str(e, print_readably)).join(" ")
@@ -23,9 +96,26 @@ pub fn pr_str(form: &MalVal, print_readably: bool) -> String {
             "[{}]",
             v.iter().map(|v| pr_str(v, print_readably)).join(" ")
         ),
-        MalVal::HashMap(m, _) => format!(
+        // 表示する要素数を超える部分は...にする. 計算に失敗した要素はエラーとして表示する
+        MalVal::LazySeq(_) => {
+            let mut xs = form.seq().unwrap();
+            let mut items = xs
+                .by_ref()
+                .take(print_length())
+                .map(|x| match x {
+                    Ok(x) => pr_str(&x, print_readably),
+                    Err(e) => format!("#<error {}>", e),
+                })
+                .collect_vec();
+            if xs.next().is_some() {
+                items.push(String::from("..."));
+            }
+            format!("({})", items.join(" "))
+        }
+        MalVal::HashMap(_, _) | MalVal::SortedMap(_, _) => format!(
             "{{{}}}",
-            m.iter()
+            form.map_iter()
+                .unwrap()
                 .map(|(k, v)| format!(
                     "{} {}",
                     pr_str(k, print_readably),
@@ -33,14 +123,52 @@ pub fn pr_str(form: &MalVal, print_readably: bool) -> String {
                 ))
                 .join(" ")
         ),
-        MalVal::HashSet(s, _) => format!(
+        MalVal::HashSet(_, _) | MalVal::SortedSet(_, _) => format!(
             "#{{{}}}",
-            s.iter().map(|s| pr_str(s, print_readably)).join(" ")
+            form.set_iter()
+                .unwrap()
+                .map(|s| pr_str(s, print_readably))
+                .join(" ")
         ),
-        MalVal::BuiltinFn(_) | MalVal::Func(_, _) => String::from("#<function>"),
+        MalVal::BuiltinFn(_) => String::from("#<function>"),
+        MalVal::NativeFn(f) => format!("#<function {}>", f.name),
+        MalVal::Func(f, _) => match &f.name {
+            Some(name) => format!("#<function {}>", name),
+            None => String::from("#<function>"),
+        },
+        MalVal::Error(e) => format!("#<error {}>", e),
+        MalVal::Transient(_) => String::from("#<transient>"),
+        MalVal::Reduced(x) => format!("#<reduced {}>", pr_str(x, print_readably)),
+    }
+}
+
+// 読み戻せる形式で表示する: 整数値でも小数点を付ける
+fn print_float(f: f64) -> String {
+    if f.is_nan() {
+        String::from("##NaN")
+    } else if f.is_infinite() {
+        String::from(if f > 0.0 { "##Inf" } else { "##-Inf" })
+    } else {
+        format!("{:?}", f)
     }
 }
 
+// 正規表現のパターンはそのまま表示し、エスケープされていない"だけをエスケープする
+fn escape_regex(s: &str) -> String {
+    s.chars()
+        .scan(false, |escaped, c| {
+            let quote = c == '"' && !*escaped;
+            *escaped = c == '\\' && !*escaped;
+            Some(if quote {
+                [Some('\\'), Some(c)]
+            } else {
+                [Some(c), None]
+            })
+        })
+        .flat_map(|c| c.into_iter().flatten())
+        .collect()
+}
+
 fn escape(s: &str) -> String {
     s.chars()
         .map(|c| match c {
@@ -54,3 +182,171 @@ fn escape(s: &str) -> String {
         .flat_map(|c| c.into_iter().flatten())
         .collect()
 }
+
+// formatの書式指定 %[フラグ][幅][.精度]変換 を解析したもの
+enum Piece {
+    Text(String),
+    Spec {
+        left: bool, // -: 左寄せ
+        zero: bool, // 0: 数値を0で埋める
+        plus: bool, // +: 正の数に符号を付ける
+        width: usize,
+        precision: Option<usize>,
+        conv: char,
+    },
+}
+
+/// 書式文字列に従って引数を文字列にする
+/// %s %S %d %x %X %f %% に対応し、引数の数が書式指定と合わなければエラー
+pub fn format(fmt: &str, args: &[MalVal]) -> Result<String, MalError> {
+    let pieces = parse_format(fmt)?;
+    let specs = pieces
+        .iter()
+        .filter(|p| matches!(p, Piece::Spec { conv, .. } if *conv != '%'))
+        .count();
+    if specs != args.len() {
+        // 書式文字列も引数として数える
+        return Err(MalError::WrongArity(
+            "format".to_string(),
+            Arity::Fixed(specs + 1),
+            args.len() + 1,
+        ));
+    }
+    let mut args = args.iter();
+    let mut out = String::new();
+    for piece in pieces {
+        let (left, zero, plus, width, precision, conv) = match piece {
+            Piece::Text(text) => {
+                out.push_str(&text);
+                continue;
+            }
+            Piece::Spec {
+                left,
+                zero,
+                plus,
+                width,
+                precision,
+                conv,
+            } => (left, zero, plus, width, precision, conv),
+        };
+        let body = match conv {
+            '%' => String::from("%"),
+            // SAFETY: 書式指定の数と引数の数は等しい
+            _ => format_arg(args.next().unwrap(), conv, plus, precision)?,
+        };
+        let len = body.chars().count();
+        if len >= width {
+            out.push_str(&body);
+        } else if left {
+            out.push_str(&body);
+            out.extend(std::iter::repeat_n(' ', width - len));
+        } else if zero && matches!(conv, 'd' | 'x' | 'X' | 'f') {
+            // 符号の後ろを0で埋める
+            let digits = body.trim_start_matches(['-', '+']);
+            out.push_str(&body[..body.len() - digits.len()]);
+            out.extend(std::iter::repeat_n('0', width - len));
+            out.push_str(digits);
+        } else {
+            out.extend(std::iter::repeat_n(' ', width - len));
+            out.push_str(&body);
+        }
+    }
+    Ok(out)
+}
+
+fn parse_format(fmt: &str) -> Result<Vec<Piece>, MalError> {
+    let mut pieces = vec![];
+    let mut text = String::new();
+    let mut chars = fmt.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            text.push(c);
+            continue;
+        }
+        pieces.push(Piece::Text(std::mem::take(&mut text)));
+        let (mut left, mut zero, mut plus) = (false, false, false);
+        while let Some(flag) = chars.next_if(|c| matches!(c, '-' | '0' | '+')) {
+            match flag {
+                '-' => left = true,
+                '0' => zero = true,
+                _ => plus = true,
+            }
+        }
+        let width = parse_digits(&mut chars).unwrap_or(0);
+        let precision = chars
+            .next_if_eq(&'.')
+            .map(|_| parse_digits(&mut chars).unwrap_or(0));
+        match chars.next() {
+            Some(conv @ ('s' | 'S' | 'd' | 'x' | 'X' | 'f' | '%')) => pieces.push(Piece::Spec {
+                left,
+                zero,
+                plus,
+                width,
+                precision,
+                conv,
+            }),
+            Some(conv) => {
+                return Err(MalError::InvalidSyntax(format!(
+                    "unknown format conversion %{}",
+                    conv
+                )))
+            }
+            None => {
+                return Err(MalError::InvalidSyntax(
+                    "incomplete format specifier at end of string".to_string(),
+                ))
+            }
+        }
+    }
+    pieces.push(Piece::Text(text));
+    Ok(pieces)
+}
+
+fn parse_digits(chars: &mut Peekable<Chars>) -> Option<usize> {
+    let mut digits = String::new();
+    while let Some(d) = chars.next_if(char::is_ascii_digit) {
+        digits.push(d);
+    }
+    digits.parse().ok()
+}
+
+// 一つの引数を変換する. 幅による埋め合わせはformatで行う
+fn format_arg(
+    arg: &MalVal,
+    conv: char,
+    plus: bool,
+    precision: Option<usize>,
+) -> Result<String, MalError> {
+    let sign = |s: String| {
+        if plus && !s.starts_with('-') {
+            format!("+{}", s)
+        } else {
+            s
+        }
+    };
+    match conv {
+        's' | 'S' => {
+            let s = try_pr_str(arg, conv == 'S')?;
+            Ok(match precision {
+                Some(p) => s.chars().take(p).collect(),
+                None => s,
+            })
+        }
+        'd' | 'x' | 'X' => {
+            let n = arg
+                .to_bigint()
+                .ok_or(MalError::InvalidType(arg.clone(), "integer".to_string()))?;
+            Ok(sign(match conv {
+                'd' => n.to_string(),
+                'x' => n.to_str_radix(16),
+                _ => n.to_str_radix(16).to_uppercase(),
+            }))
+        }
+        _ => {
+            let f = arg
+                .to_f64()
+                .ok_or(MalError::InvalidType(arg.clone(), "number".to_string()))?;
+            Ok(sign(format!("{:.*}", precision.unwrap_or(6), f)))
+        }
+    }
+}
@@ -1,5 +1,6 @@
-use crate::types::{MalError, MalResult, MalVal, Paren};
-use fnv::{FnvHashMap, FnvHashSet};
+use crate::types::{BigInt, BigRational, MalError, MalResult, MalVal, Paren};
+use im_rc::{HashMap, HashSet, Vector};
+use num_traits::Zero;
 use std::iter::Peekable;
 
 macro_rules! regex {
@@ -23,22 +24,110 @@ pub fn read_str(input: String) -> MalResult {
 //  - (?:): non-capturing group. グループ化した文字列にマッチするが、グループ化した文字列を取得しない
 
 // 文字列を受け取り、トークンのベクタを返す: 正規表現を使う
-// malのトークンすべてにマッチする正規表現: [\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]*)
+// malのトークンすべてにマッチする正規表現: [\s,]*(~@|[\[\]{}()'`~^@]|#?"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]*)
 // - [\s,]*: 任意個の空白とカンマ
 // - ~@: '~@'自体
 // - #\{: '#{'自体
 // - [\[\]{}()'`~^@]: []{}()'`~^@のいずれか
-// - "(?:\\.|[^\\"])*"?: "で囲まれた文字列(閉じていない場合を含む)
+// - #f": 文字列補間の始まり. 閉じる"まではscan_interpolationで読む
+// - #?"(?:\\.|[^\\"])*"?: "で囲まれた文字列(閉じていない場合を含む). #が付いていれば正規表現
 //  - \\.: \と任意の文字(エスケープされた文字)
 //  - [^\\"]: \と"以外の任意の文字
 // - ;.*: コメント行
 // - [^\s\[\]{}()'"`,;]*: 空白と[]{}()'"`,;以外の任意の文字
 fn tokenize(input: String) -> Vec<String> {
-    regex!(r#"[\s,]*(~@|#\{|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}()'"`,;]*)"#)
-        .captures_iter(&input)
-        .map(|cap| cap[1].to_string()) // cap[0]はマッチした文字列全体, cap[1]はグループ化した文字列=空白以外の部分
-        .filter(|token| !token.starts_with(';')) // コメント行を除外
-        .collect()
+    let re = regex!(
+        r#"[\s,]*(~@|#\{|[\[\]{}()'`~^@]|#f"|#?"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}()'"`,;]*)"#
+    );
+    let mut tokens = vec![];
+    let mut pos = 0;
+    while let Some(cap) = re.captures_at(&input, pos) {
+        // cap[0]はマッチした文字列全体, cap[1]はグループ化した文字列=空白以外の部分
+        let (token, mut end) = (cap.get(1).unwrap(), cap.get(0).unwrap().end());
+        if token.is_empty() {
+            break; // 入力の終わり
+        }
+        if token.as_str() == "#f\"" {
+            // 閉じていなければ残り全体を一つのトークンとし、エラーはread_atomで報告する
+            end += scan_interpolation(&input[end..]).map_or(input.len() - end, |(_, len)| len);
+        }
+        if !token.as_str().starts_with(';') {
+            // コメント行を除外
+            tokens.push(input[token.start()..end].to_string());
+        }
+        pos = end;
+    }
+    tokens
+}
+
+// 文字列補間の断片
+enum Segment<'a> {
+    Text(String),  // エスケープを解除した文字列
+    Form(&'a str), // ~{}の中のコード
+}
+
+// #f"の後ろを閉じる"まで読み、文字列とコードの断片に分ける. 閉じる"までの長さも返す
+// ~{}の中では文字列リテラルと入れ子の{}を読み飛ばして対応する}を探す
+fn scan_interpolation(s: &str) -> Result<(Vec<Segment<'_>>, usize), MalError> {
+    let mut segments = vec![];
+    let mut text = String::new();
+    let mut i = 0;
+    while let Some(c) = s[i..].chars().next() {
+        match c {
+            '"' => {
+                segments.push(Segment::Text(unescape(&text)));
+                return Ok((segments, i + 1));
+            }
+            '\\' => {
+                text.push(c);
+                i += 1;
+                if let Some(c) = s[i..].chars().next() {
+                    text.push(c);
+                    i += c.len_utf8();
+                }
+                continue;
+            }
+            '~' if s[i + 1..].starts_with('{') => {
+                let start = i + 2;
+                let end = start
+                    + close_brace(&s[start..]).ok_or_else(|| {
+                        let rest = s[i..].lines().next().unwrap_or_default();
+                        MalError::InvalidSyntax(format!("unclosed interpolation {}", rest))
+                    })?;
+                segments.push(Segment::Text(unescape(&std::mem::take(&mut text))));
+                segments.push(Segment::Form(&s[start..end]));
+                i = end + 1;
+                continue;
+            }
+            _ => text.push(c),
+        }
+        i += c.len_utf8();
+    }
+    Err(MalError::UncloedQuote)
+}
+
+// ~{の後ろから対応する}の位置を探す
+fn close_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => loop {
+                match chars.next()?.1 {
+                    '\\' => {
+                        chars.next();
+                    }
+                    '"' => break,
+                    _ => {}
+                }
+            },
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
 }
 
 fn unescape(s: &str) -> String {
@@ -94,21 +183,21 @@ fn read_list(reader: &mut Reader) -> MalResult {
 }
 
 fn read_vec(reader: &mut Reader) -> MalResult {
-    let mut v = Vec::new();
+    let mut v = Vector::new();
     reader.next(); // '['を読み飛ばす
     while let Some(token) = reader.peek() {
         if token == "]" {
             reader.next(); // ']'を読み飛ばす
             return Ok(MalVal::vec(v));
         }
-        v.push(read_form(reader)?);
+        v.push_back(read_form(reader)?);
     }
 
     Err(MalError::Unbalanced(Paren::Square))
 }
 
 fn read_hashmap(reader: &mut Reader) -> MalResult {
-    let mut m = FnvHashMap::default();
+    let mut m = HashMap::default();
     reader.next(); // "{"を読み飛ばす
     while let Some(token) = reader.peek() {
         if token == "}" {
@@ -130,7 +219,7 @@ fn read_hashmap(reader: &mut Reader) -> MalResult {
 }
 
 fn read_hashset(reader: &mut Reader) -> MalResult {
-    let mut s = FnvHashSet::default();
+    let mut s = HashSet::default();
     reader.next(); // "#{"を読み飛ばす
     while let Some(token) = reader.peek() {
         if token == "}" {
@@ -146,11 +235,27 @@ fn read_hashset(reader: &mut Reader) -> MalResult {
 fn read_atom(reader: &mut Reader) -> MalResult {
     match reader.next().unwrap().as_ref() {
         "nil" => Ok(MalVal::Nil),
+        "##Inf" => Ok(MalVal::Float(f64::INFINITY)),
+        "##-Inf" => Ok(MalVal::Float(f64::NEG_INFINITY)),
+        "##NaN" => Ok(MalVal::Float(f64::NAN)),
         token => {
             if let Ok(b) = token.parse::<bool>() {
                 Ok(MalVal::Bool(b))
             } else if let Ok(n) = token.parse::<i64>() {
                 Ok(MalVal::Number(n))
+            } else if regex!(r"^[+-]?\d+$").is_match(token) {
+                // BigIntのparseは_を含む1_000なども受け付けるので、数字だけの場合に限る
+                Ok(MalVal::from(token.parse::<BigInt>().unwrap()))
+            } else if let Some(cap) = regex!(r"^([+-]?\d+)/(\d+)$").captures(token) {
+                let (n, d): (BigInt, BigInt) = (cap[1].parse().unwrap(), cap[2].parse().unwrap());
+                if d.is_zero() {
+                    Err(MalError::DividedByZero)
+                } else {
+                    Ok(MalVal::from(BigRational::new(n, d)))
+                }
+            } else if regex!(r"^[+-]?\d+(\.\d*)?([eE][+-]?\d+)?$").is_match(token) {
+                // 整数は上で読み取っているので、ここに来るのは小数点か指数を含む場合
+                Ok(MalVal::Float(token.parse().unwrap()))
             } else if token.starts_with('\"') {
                 if token.len() >= 2 && regex!(r#"[^\\](\\\\)*"$"#).is_match(token) {
                     // 末尾がエスケープされていない"で終わる場合 => 文字列リテラル
@@ -158,6 +263,15 @@ fn read_atom(reader: &mut Reader) -> MalResult {
                 } else {
                     Err(MalError::UncloedQuote)
                 }
+            } else if let Some(s) = token.strip_prefix("#f\"") {
+                read_interpolation(s)
+            } else if let Some(pattern) = token.strip_prefix('#').filter(|t| t.starts_with('\"')) {
+                // 正規表現リテラル: \"以外のエスケープはそのまま正規表現に渡す
+                if pattern.len() >= 2 && regex!(r#"[^\\](\\\\)*"$"#).is_match(pattern) {
+                    MalVal::regex(&pattern[1..pattern.len() - 1].replace("\\\"", "\""))
+                } else {
+                    Err(MalError::UncloedQuote)
+                }
             } else if let Some(keyword) = token.strip_prefix(':') {
                 Ok(MalVal::keyword(keyword))
             } else {
@@ -167,6 +281,34 @@ fn read_atom(reader: &mut Reader) -> MalResult {
     }
 }
 
+// #f"Hello ~{name}!" => (str "Hello " name "!")
+fn read_interpolation(s: &str) -> MalResult {
+    let (segments, _) = scan_interpolation(s)?;
+    let mut forms = vec![MalVal::symbol("str")];
+    for segment in segments {
+        match segment {
+            Segment::Text(text) if text.is_empty() => {}
+            Segment::Text(text) => forms.push(MalVal::string(text)),
+            Segment::Form(code) => {
+                // ~{}の中はちょうど一つのフォームでなければならない
+                let mut reader = tokenize(code.to_string()).into_iter().peekable();
+                let form = read_form(&mut reader).and_then(|form| match reader.peek() {
+                    Some(token) => Err(MalError::InvalidSyntax(format!(
+                        "unexpected {} after the first form",
+                        token
+                    ))),
+                    None => Ok(form),
+                });
+                forms.push(form.map_err(|e| {
+                    MalError::InvalidSyntax(format!("broken interpolation ~{{{}}}", code))
+                        .with_cause(e)
+                })?);
+            }
+        }
+    }
+    Ok(MalVal::list(forms))
+}
+
 fn read_reader_macro(reader: &mut Reader) -> MalResult {
     Ok(MalVal::list(Vec::from_iter([
         MalVal::symbol(match reader.next().unwrap().as_str() {
@@ -191,4 +333,20 @@ mod tests {
             vec!["(", "+", "134", "234", ")"]
         );
     }
+
+    #[test]
+    fn test_read_number() {
+        use super::read_str;
+        use crate::types::{BigInt, MalVal};
+
+        assert_eq!(
+            read_str("123456789012345678901234567890".to_string()).unwrap(),
+            MalVal::from("123456789012345678901234567890".parse::<BigInt>().unwrap())
+        );
+        // _を含むトークンは数値ではなくシンボル
+        assert_eq!(
+            read_str("1_000".to_string()).unwrap(),
+            MalVal::symbol("1_000")
+        );
+    }
 }
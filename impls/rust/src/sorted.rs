@@ -0,0 +1,199 @@
+use std::cmp::Ordering;
+
+use im_rc::vector::Iter;
+use im_rc::{vector, Vector};
+
+use crate::types::{MalError, MalVal};
+
+/// ソート済みコレクションの要素: キーで順序付けられる
+pub trait Entry: Clone {
+    fn key(&self) -> &MalVal;
+    fn to_mal(&self) -> MalVal;
+}
+
+// sorted-setの要素
+impl Entry for MalVal {
+    fn key(&self) -> &MalVal {
+        self
+    }
+
+    fn to_mal(&self) -> MalVal {
+        self.clone()
+    }
+}
+
+// sorted-mapの要素: (キー, 値)
+impl Entry for (MalVal, MalVal) {
+    fn key(&self) -> &MalVal {
+        &self.0
+    }
+
+    fn to_mal(&self) -> MalVal {
+        MalVal::vec(vector![self.0.clone(), self.1.clone()])
+    }
+}
+
+/// キーの順に要素を並べたコレクション. sorted-mapとsorted-setの実体
+/// 比較関数が与えられなければMalValの全順序で並べる
+#[derive(Debug, Clone)]
+pub struct Sorted<T: Entry> {
+    items: Vector<T>,
+    comparator: Option<MalVal>,
+}
+
+impl<T: Entry> Sorted<T> {
+    pub fn new(comparator: Option<MalVal>) -> Self {
+        Sorted {
+            items: Vector::new(),
+            comparator,
+        }
+    }
+
+    /// 同じ比較関数を持つ空のコレクション
+    pub fn empty(&self) -> Self {
+        Sorted::new(self.comparator.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.items.iter()
+    }
+
+    pub fn compare(&self, a: &MalVal, b: &MalVal) -> Result<Ordering, MalError> {
+        compare_by(self.comparator.as_ref(), a, b)
+    }
+
+    // 二分探索: 見つかればその位置、なければ挿入すべき位置をErrで返す
+    fn search(&self, key: &MalVal) -> Result<Result<usize, usize>, MalError> {
+        let (mut lo, mut hi) = (0, self.items.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.compare(self.items[mid].key(), key)? {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return Ok(Ok(mid)),
+            }
+        }
+        Ok(Err(lo))
+    }
+
+    // predが先頭から連続して真になる要素数
+    fn partition_point<F>(&self, mut pred: F) -> Result<usize, MalError>
+    where
+        F: FnMut(&T) -> Result<bool, MalError>,
+    {
+        let (mut lo, mut hi) = (0, self.items.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if pred(&self.items[mid])? {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        Ok(lo)
+    }
+
+    pub fn get(&self, key: &MalVal) -> Result<Option<&T>, MalError> {
+        Ok(self.search(key)?.ok().map(|i| &self.items[i]))
+    }
+
+    /// 同じキーの要素があれば置き換える
+    pub fn insert(&mut self, item: T) -> Result<(), MalError> {
+        match self.search(item.key())? {
+            Ok(i) => {
+                self.items.set(i, item);
+            }
+            Err(i) => self.items.insert(i, item),
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &MalVal) -> Result<(), MalError> {
+        if let Ok(i) = self.search(key)? {
+            self.items.remove(i);
+        }
+        Ok(())
+    }
+
+    /// subseqの範囲検索: (test, key)の組それぞれについて
+    /// (test (compare 要素のキー key) 0) が真になる要素を返す
+    /// testは<, <=, >, >=のような単調な比較関数でなければならない
+    pub fn subseq(&self, bounds: &[(MalVal, MalVal)]) -> Result<Vector<T>, MalError> {
+        let (mut start, mut end) = (0, self.items.len());
+        for (test, bound) in bounds {
+            let includes = |ord: Ordering| -> Result<bool, MalError> {
+                let ord = MalVal::Number(ord as i64);
+                Ok(!matches!(
+                    test.apply(vec![ord, MalVal::Number(0)])?,
+                    MalVal::Nil | MalVal::Bool(false)
+                ))
+            };
+            // 小さい要素が含まれるなら範囲の終わり、そうでなければ始まりを決める
+            if includes(Ordering::Less)? {
+                end = end.min(self.partition_point(|e| includes(self.compare(e.key(), bound)?))?);
+            } else {
+                start = start
+                    .max(self.partition_point(|e| Ok(!includes(self.compare(e.key(), bound)?)?))?);
+            }
+        }
+        Ok(if start < end {
+            self.items.clone().slice(start..end)
+        } else {
+            Vector::new()
+        })
+    }
+}
+
+/// 比較関数でaとbを比較する. 比較関数がなければMalValの全順序で比較する
+/// 比較関数は数値 (負, 0, 正) か、「aがbより小さいか」を表す真偽値を返す
+pub fn compare_by(
+    comparator: Option<&MalVal>,
+    a: &MalVal,
+    b: &MalVal,
+) -> Result<Ordering, MalError> {
+    let Some(f) = comparator else {
+        return Ok(a.cmp(b));
+    };
+    match f.apply(vec![a.clone(), b.clone()])? {
+        MalVal::Number(n) => Ok(n.cmp(&0)),
+        MalVal::Nil | MalVal::Bool(false) => match f.apply(vec![b.clone(), a.clone()])? {
+            MalVal::Nil | MalVal::Bool(false) => Ok(Ordering::Equal),
+            _ => Ok(Ordering::Greater),
+        },
+        _ => Ok(Ordering::Less),
+    }
+}
+
+/// 安定なマージソート. 比較関数がエラーを返した場合はそこで中断する
+/// NOTE: 比較関数が全順序とは限らないので、slice::sort_byは使わない
+pub fn sort_by<T, F>(mut items: Vec<T>, cmp: &mut F) -> Result<Vec<T>, MalError>
+where
+    F: FnMut(&T, &T) -> Result<Ordering, MalError>,
+{
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let (left, right) = (sort_by(items, cmp)?, sort_by(right, cmp)?);
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // 等しい場合は左側を先にして安定にする
+        if cmp(r, l)? == Ordering::Less {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
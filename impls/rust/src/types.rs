@@ -1,35 +1,107 @@
 use fnv::FnvBuildHasher;
-use std::collections::{HashMap, HashSet};
-use std::fmt::{Display, Formatter, Result as FmtResult};
+use im_rc::{HashMap, HashSet, Vector};
+use itertools::{Either, Itertools};
+pub use num_bigint::BigInt;
+pub use num_rational::BigRational;
+use num_traits::ToPrimitive;
+use regex::Regex;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
 use std::hash::{BuildHasher, Hash, Hasher};
 use std::rc::Rc;
 
 use crate::env::Env;
+use crate::lazy::{self, LazySeq, Step};
+use crate::list::List;
 use crate::printer;
+use crate::sorted::Sorted;
 
 #[derive(Debug, Clone)]
-pub enum MalVal<S = FnvBuildHasher> {
+pub enum MalVal<S: BuildHasher = FnvBuildHasher> {
     Nil,
     Bool(bool),
     Number(i64),
+    BigInt(Rc<BigInt>), // i64に収まらない整数. 収まる値は常にNumberで表す
+    Float(f64),
+    Ratio(Rc<BigRational>), // 既約分数. 分母が1の場合は常に整数で表す
     String(Rc<String>),
     Keyword(Rc<String>),
     Symbol(Rc<String>),
-    List(Rc<Vec<MalVal>>, Rc<MalVal>),
-    Vector(Rc<Vec<MalVal>>, Rc<MalVal>),
+    Regex(Rc<Regex>),
+    List(List, Rc<MalVal>),
+    Vector(Rc<Vector<MalVal>>, Rc<MalVal>),
+    LazySeq(Rc<LazySeq>),
     HashMap(Rc<HashMap<MalVal, MalVal, S>>, Rc<MalVal>),
     HashSet(Rc<HashSet<MalVal, S>>, Rc<MalVal>),
+    SortedMap(Rc<Sorted<(MalVal, MalVal)>>, Rc<MalVal>),
+    SortedSet(Rc<Sorted<MalVal>>, Rc<MalVal>),
     BuiltinFn(fn(Vec<MalVal>) -> MalResult),
+    NativeFn(Rc<NativeFn>),
     Func(Rc<Closure<S>>, Rc<MalVal>),
+    Error(Rc<MalError>),
+    // transientで作られた変更可能なコレクション. persistent!の後はNone
+    Transient(Rc<RefCell<Option<MalVal>>>),
+    // reduceを途中で打ち切るために (reduced x) で包んだ値
+    Reduced(Rc<MalVal>),
+}
+
+/// 評価済みの関数を引数に適用する評価器の関数
+pub type Applier = fn(&MalVal, Vec<MalVal>) -> MalResult;
+
+thread_local! {
+    // ユーザー定義関数を呼び出すための評価器の関数
+    static APPLIER: Cell<Option<Applier>> = const { Cell::new(None) };
+    // コンパイル済みの正規表現: パターン -> 正規表現
+    static REGEX_CACHE: RefCell<std::collections::HashMap<String, Rc<Regex>>> =
+        RefCell::new(std::collections::HashMap::new());
+}
+
+// キャッシュする正規表現の数の上限. 超えたら全て捨てる
+const REGEX_CACHE_SIZE: usize = 256;
+
+/// 値を捕捉した組み込み関数. compやtransducerなどが返す関数に用いる
+pub struct NativeFn {
+    pub name: &'static str,
+    f: Box<dyn Fn(Vec<MalVal>) -> MalResult>,
+}
+
+impl NativeFn {
+    pub fn call(&self, args: Vec<MalVal>) -> MalResult {
+        (self.f)(args)
+    }
+}
+
+impl Debug for NativeFn {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "NativeFn({})", self.name)
+    }
 }
 
 #[derive(Debug, Clone)]
-pub struct Closure<S = FnvBuildHasher> {
+pub struct Closure<S: BuildHasher = FnvBuildHasher> {
+    pub name: Option<Rc<str>>, // (fn* name [args] body) で与えられた名前
     pub rev_params: (Vec<String>, Option<String>),
     pub body: MalVal<S>,
     pub env: Env,
 }
 
+/// パターンをコンパイルした正規表現. 同じパターンはキャッシュしたものを使う
+pub fn cached_regex(pattern: &str) -> Result<Rc<Regex>, MalError> {
+    REGEX_CACHE.with_borrow_mut(|cache| {
+        if let Some(re) = cache.get(pattern) {
+            return Ok(re.clone());
+        }
+        let re = Rc::new(Regex::new(pattern).map_err(|e| MalError::InvalidRegex(e.to_string()))?);
+        if cache.len() >= REGEX_CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(pattern.to_string(), re.clone());
+        Ok(re)
+    })
+}
+
 impl<S> MalVal<S>
 where
     S: BuildHasher + Clone,
@@ -46,19 +118,23 @@ where
         MalVal::Symbol(Rc::new(str.into()))
     }
 
+    pub fn regex(pattern: &str) -> Result<Self, MalError> {
+        cached_regex(pattern).map(MalVal::Regex)
+    }
+
     pub fn list(list: Vec<MalVal>) -> Self {
         MalVal::list_with_meta(list, MalVal::Nil)
     }
 
     pub fn list_with_meta(list: Vec<MalVal>, meta: MalVal) -> Self {
-        MalVal::List(Rc::new(list), Rc::new(meta))
+        MalVal::List(list.into(), Rc::new(meta))
     }
 
-    pub fn vec(vec: Vec<MalVal>) -> Self {
+    pub fn vec(vec: Vector<MalVal>) -> Self {
         MalVal::vec_with_meta(vec, MalVal::Nil)
     }
 
-    pub fn vec_with_meta(vec: Vec<MalVal>, meta: MalVal) -> Self {
+    pub fn vec_with_meta(vec: Vector<MalVal>, meta: MalVal) -> Self {
         MalVal::Vector(Rc::new(vec), Rc::new(meta))
     }
 
@@ -78,6 +154,14 @@ where
         MalVal::HashSet(Rc::new(hashset), Rc::new(meta))
     }
 
+    pub fn sorted_map(sorted: Sorted<(MalVal, MalVal)>) -> Self {
+        MalVal::SortedMap(Rc::new(sorted), Rc::new(MalVal::Nil))
+    }
+
+    pub fn sorted_set(sorted: Sorted<MalVal>) -> Self {
+        MalVal::SortedSet(Rc::new(sorted), Rc::new(MalVal::Nil))
+    }
+
     pub fn func_with_meta(closure: Closure<S>, meta: MalVal) -> Self {
         MalVal::Func(Rc::new(closure), Rc::new(meta))
     }
@@ -86,52 +170,497 @@ where
         MalVal::func_with_meta(closure, MalVal::Nil)
     }
 
+    pub fn native<F>(name: &'static str, f: F) -> Self
+    where
+        F: Fn(Vec<MalVal>) -> MalResult + 'static,
+    {
+        MalVal::NativeFn(Rc::new(NativeFn {
+            name,
+            f: Box::new(f),
+        }))
+    }
+
     pub fn type_str(&self) -> String {
         match self {
             MalVal::Nil => "nil".to_string(),
             MalVal::Bool(_) => "bool".to_string(),
-            MalVal::Number(_) => "number".to_string(),
+            MalVal::Number(_) | MalVal::BigInt(_) | MalVal::Float(_) | MalVal::Ratio(_) => {
+                "number".to_string()
+            }
             MalVal::String(_) => "string".to_string(),
             MalVal::Keyword(_) => "keyword".to_string(),
             MalVal::Symbol(_) => "symbol".to_string(),
+            MalVal::Regex(_) => "regex".to_string(),
             MalVal::List(_, _) => "list".to_string(),
             MalVal::Vector(_, _) => "vector".to_string(),
+            MalVal::LazySeq(_) => "lazy-seq".to_string(),
             MalVal::HashMap(_, _) => "hash-map".to_string(),
             MalVal::HashSet(_, _) => "hash-set".to_string(),
-            MalVal::BuiltinFn(_) | MalVal::Func(_, _) => "function".to_string(),
+            MalVal::SortedMap(_, _) => "sorted-map".to_string(),
+            MalVal::SortedSet(_, _) => "sorted-set".to_string(),
+            MalVal::BuiltinFn(_) | MalVal::NativeFn(_) | MalVal::Func(_, _) => {
+                "function".to_string()
+            }
+            MalVal::Error(_) => "error".to_string(),
+            MalVal::Transient(_) => "transient".to_string(),
+            MalVal::Reduced(_) => "reduced".to_string(),
+        }
+    }
+}
+
+/// シーケンスとして扱える値の要素を順に返すイテレータ
+/// 遅延シーケンスの要素の計算に失敗した場合はErrを返す
+pub type Seq<'a> = Box<dyn Iterator<Item = MalResult> + 'a>;
+
+/// シーケンスとして扱える値の型
+pub const SEQABLE: &str = "nil, string, list, vector, lazy-seq, map or set";
+
+impl MalVal {
+    /// シーケンスとして扱える値の要素を順に返す. 扱えない値はNone
+    /// nilは空, マップは[k v]のベクタ, 文字列は一文字ずつの文字列を要素とする
+    pub fn seq(&self) -> Option<Seq<'_>> {
+        Some(match self {
+            MalVal::Nil => Box::new(std::iter::empty()),
+            MalVal::String(s) => Box::new(s.chars().map(|c| Ok(MalVal::string(c)))),
+            MalVal::LazySeq(_) => Box::new(lazy::Iter::new(self.clone())),
+            _ => {
+                if let Some(xs) = self.seq_iter() {
+                    Box::new(xs.cloned().map(Ok))
+                } else if let Some(xs) = self.set_iter() {
+                    Box::new(xs.cloned().map(Ok))
+                } else {
+                    let m = self.map_iter()?;
+                    Box::new(
+                        m.map(|(k, v)| Ok(MalVal::vec(Vector::from_iter([k.clone(), v.clone()])))),
+                    )
+                }
+            }
+        })
+    }
+
+    /// 先頭の要素と残りのシーケンスに分ける. 遅延シーケンスは先頭だけを計算する
+    /// リストと遅延シーケンスの残りはそのまま共有し、それ以外は残りをリストにする
+    pub fn uncons(&self) -> Result<Step, MalError> {
+        match self {
+            MalVal::LazySeq(seq) => seq.step(),
+            MalVal::List(l, _) => Ok(l
+                .first()
+                .map(|x| (x.clone(), MalVal::List(l.rest(), Rc::new(MalVal::Nil))))),
+            _ => {
+                let mut xs = self
+                    .seq()
+                    .ok_or(MalError::InvalidType(self.clone(), SEQABLE.to_string()))?;
+                match xs.next().transpose()? {
+                    Some(first) => Ok(Some((first, MalVal::list(xs.try_collect()?)))),
+                    None => Ok(None),
+                }
+            }
+        }
+    }
+
+    /// List, Vector, LazySeqのように順序のあるシーケンスか
+    pub fn is_sequential(&self) -> bool {
+        matches!(
+            self,
+            MalVal::List(_, _) | MalVal::Vector(_, _) | MalVal::LazySeq(_)
+        )
+    }
+
+    /// シーケンスとして扱える値の要素数. 遅延シーケンスは計算しないとわからないのでNone
+    pub fn count(&self) -> Option<usize> {
+        match self {
+            MalVal::Nil => Some(0),
+            MalVal::String(s) => Some(s.chars().count()),
+            _ => self
+                .seq_iter()
+                .map(|xs| xs.len())
+                .or_else(|| self.set_iter().map(|xs| xs.len()))
+                .or_else(|| self.map_iter().map(|m| m.len())),
+        }
+    }
+
+    /// ListかVectorの場合に要素を先頭から順に返すイテレータ
+    pub fn seq_iter(&self) -> Option<impl ExactSizeIterator<Item = &MalVal>> {
+        match self {
+            MalVal::List(l, _) => Some(Either::Left(l.iter())),
+            MalVal::Vector(v, _) => Some(Either::Right(v.iter())),
+            _ => None,
+        }
+    }
+
+    /// HashMapかSortedMapの場合に要素を返すイテレータ
+    pub fn map_iter(&self) -> Option<impl ExactSizeIterator<Item = (&MalVal, &MalVal)>> {
+        match self {
+            MalVal::HashMap(m, _) => Some(Either::Left(m.iter())),
+            MalVal::SortedMap(m, _) => Some(Either::Right(m.iter().map(|(k, v)| (k, v)))),
+            _ => None,
+        }
+    }
+
+    /// HashSetかSortedSetの場合に要素を返すイテレータ
+    pub fn set_iter(&self) -> Option<impl ExactSizeIterator<Item = &MalVal>> {
+        match self {
+            MalVal::HashSet(s, _) => Some(Either::Left(s.iter())),
+            MalVal::SortedSet(s, _) => Some(Either::Right(s.iter())),
+            _ => None,
+        }
+    }
+
+    /// マップからキーに対応する値を探す. 比較関数が失敗した場合は見つからなかったものとする
+    pub fn map_get(&self, key: &MalVal) -> Option<&MalVal> {
+        match self {
+            MalVal::HashMap(m, _) => m.get(key),
+            MalVal::SortedMap(m, _) => m.get(key).ok().flatten().map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// 集合が要素を含むか. 比較関数が失敗した場合は含まないものとする
+    pub fn set_contains(&self, x: &MalVal) -> bool {
+        match self {
+            MalVal::HashSet(s, _) => s.contains(x),
+            MalVal::SortedSet(s, _) => matches!(s.get(x), Ok(Some(_))),
+            _ => false,
+        }
+    }
+
+    /// 整数ならBigIntに変換する
+    pub fn to_bigint(&self) -> Option<BigInt> {
+        match self {
+            MalVal::Number(n) => Some(BigInt::from(*n)),
+            MalVal::BigInt(n) => Some(n.as_ref().clone()),
+            _ => None,
+        }
+    }
+
+    /// 整数か分数なら分数に変換する
+    pub fn to_rational(&self) -> Option<BigRational> {
+        match self {
+            MalVal::Ratio(r) => Some(r.as_ref().clone()),
+            _ => self.to_bigint().map(BigRational::from_integer),
+        }
+    }
+
+    /// 数値ならf64に変換する
+    pub fn to_f64(&self) -> Option<f64> {
+        match self {
+            MalVal::Number(n) => Some(*n as f64),
+            MalVal::BigInt(n) => n.to_f64(),
+            MalVal::Float(f) => Some(*f),
+            MalVal::Ratio(r) => r.to_f64(),
+            _ => None,
+        }
+    }
+
+    fn is_nan(&self) -> bool {
+        matches!(self, MalVal::Float(f) if f.is_nan())
+    }
+
+    /// 数値として比較する. 数値でない場合とNaNとの比較はNone
+    /// 浮動小数点数を含む場合は浮動小数点数として比較する
+    pub fn num_cmp(&self, other: &MalVal) -> Option<Ordering> {
+        match (self, other) {
+            (MalVal::Number(a), MalVal::Number(b)) => Some(a.cmp(b)),
+            (MalVal::Float(_), _) | (_, MalVal::Float(_)) => {
+                self.to_f64()?.partial_cmp(&other.to_f64()?)
+            }
+            _ => Some(self.to_rational()?.cmp(&other.to_rational()?)),
+        }
+    }
+
+    /// <やcompare, sortで用いる順序. リストやベクタの要素も含めて数値は値で比較し、1と1.0は等しい
+    /// それ以外の値やNaNとの比較はOrdと同じ. 1と1.0を区別するsorted-mapのキーにはOrdを用いる
+    pub fn value_cmp(&self, other: &MalVal) -> Ordering {
+        if let Some(ord) = self.num_cmp(other) {
+            return ord;
+        }
+        if !(self.is_sequential() && other.is_sequential()) {
+            return self.cmp(other);
+        }
+        let (mut a, mut b) = (self.seq().unwrap(), other.seq().unwrap());
+        loop {
+            let ord = match (a.next(), b.next()) {
+                (None, None) => return Ordering::Equal,
+                (None, Some(_)) => return Ordering::Less,
+                (Some(_), None) => return Ordering::Greater,
+                (Some(Ok(x)), Some(Ok(y))) => x.value_cmp(&y),
+                // 計算に失敗した要素は全ての値より大きい
+                (Some(x), Some(y)) => x.cmp(&y),
+            };
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+    }
+
+    /// コレクションからキーに対応する要素を取り出す. ベクタは添字で取り出す
+    pub fn get(&self, key: &MalVal) -> Result<Option<MalVal>, MalError> {
+        Ok(match (self, key) {
+            (MalVal::HashMap(m, _), k) => m.get(k).cloned(),
+            (MalVal::HashSet(set, _), k) => set.contains(k).then(|| k.clone()),
+            (MalVal::SortedMap(m, _), k) => m.get(k)?.map(|(_, v)| v.clone()),
+            (MalVal::SortedSet(set, _), k) => set.get(k)?.cloned(),
+            (MalVal::Vector(v, _), MalVal::Number(i)) => {
+                usize::try_from(*i).ok().and_then(|i| v.get(i)).cloned()
+            }
+            _ => None,
+        })
+    }
+
+    /// 評価器がユーザー定義関数を呼び出す関数を登録する
+    pub fn set_applier(apply: Applier) {
+        APPLIER.set(Some(apply));
+    }
+
+    /// 関数を引数に適用する. 組み込み関数からユーザー定義関数を呼ぶときに用いる
+    /// マップ、集合、ベクタとキーワードも省略可能なデフォルト値をとる関数として呼び出せる
+    pub fn apply(&self, args: Vec<MalVal>) -> MalResult {
+        match self {
+            MalVal::BuiltinFn(f) => f(args),
+            MalVal::NativeFn(f) => f.call(args),
+            MalVal::Func(_, _) => match APPLIER.get() {
+                Some(apply) => apply(self, args),
+                None => Err(MalError::InvalidType(
+                    self.clone(),
+                    "builtin function".to_string(),
+                )),
+            },
+            // (coll key default?)
+            MalVal::HashMap(_, _)
+            | MalVal::HashSet(_, _)
+            | MalVal::SortedMap(_, _)
+            | MalVal::SortedSet(_, _)
+            | MalVal::Vector(_, _) => match (self, args.as_slice()) {
+                (MalVal::Vector(v, _), [MalVal::Number(i)]) => self
+                    .get(&MalVal::Number(*i))?
+                    .ok_or(MalError::IndexOutOfBounds(*i, v.len())),
+                (_, [key]) => Ok(self.get(key)?.unwrap_or(MalVal::Nil)),
+                (_, [key, default]) => Ok(self.get(key)?.unwrap_or(default.clone())),
+                _ => Err(MalError::WrongArity(
+                    self.type_str(),
+                    Arity::JustOrOneLess(2),
+                    args.len(),
+                )),
+            },
+            // (:key coll default?)
+            MalVal::Keyword(_) => match args.as_slice() {
+                [coll] => Ok(coll.get(self)?.unwrap_or(MalVal::Nil)),
+                [coll, default] => Ok(coll.get(self)?.unwrap_or(default.clone())),
+                _ => Err(MalError::WrongArity(
+                    self.to_string(),
+                    Arity::JustOrOneLess(2),
+                    args.len(),
+                )),
+            },
+            _ => Err(MalError::InvalidType(self.clone(), "function".to_string())),
+        }
+    }
+
+    // 型ごとの順序: nil < 真偽値 < 数値 < 文字列 < キーワード < シンボル < コレクション < その他
+    fn type_rank(&self) -> u8 {
+        match self {
+            MalVal::Nil => 0,
+            MalVal::Bool(_) => 1,
+            MalVal::Number(_) | MalVal::BigInt(_) | MalVal::Float(_) | MalVal::Ratio(_) => 2,
+            MalVal::String(_) => 3,
+            MalVal::Keyword(_) => 4,
+            MalVal::Symbol(_) => 5,
+            MalVal::Regex(_) => 6,
+            MalVal::List(_, _) | MalVal::Vector(_, _) | MalVal::LazySeq(_) => 7,
+            MalVal::HashMap(_, _) | MalVal::SortedMap(_, _) => 8,
+            MalVal::HashSet(_, _) | MalVal::SortedSet(_, _) => 9,
+            MalVal::BuiltinFn(_) | MalVal::NativeFn(_) => 10,
+            MalVal::Func(_, _) => 11,
+            MalVal::Error(_) => 12,
+            MalVal::Transient(_) => 13,
+            MalVal::Reduced(_) => 14,
+        }
+    }
+
+    /// catch*で束縛する値: throwされた値はそのまま、それ以外はエラー値として返す
+    pub fn from_error(err: MalError) -> Self {
+        match err.untraced() {
+            MalError::Throw(val) => val.clone(),
+            _ => MalVal::Error(Rc::new(err)),
+        }
+    }
+}
+
+// i64に収まる場合はNumberにする
+impl From<BigInt> for MalVal {
+    fn from(n: BigInt) -> Self {
+        match i64::try_from(&n) {
+            Ok(n) => MalVal::Number(n),
+            Err(_) => MalVal::BigInt(Rc::new(n)),
         }
     }
 }
 
+// 分母が1の場合は整数にする
+impl From<BigRational> for MalVal {
+    fn from(r: BigRational) -> Self {
+        if r.is_integer() {
+            MalVal::from(r.to_integer())
+        } else {
+            MalVal::Ratio(Rc::new(r))
+        }
+    }
+}
+
+impl<S: BuildHasher> Closure<S> {
+    /// エラーメッセージや表示に用いる関数名
+    pub fn name_str(&self) -> &str {
+        self.name.as_deref().unwrap_or("function")
+    }
+}
+
 impl PartialEq for MalVal {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (MalVal::Nil, MalVal::Nil) => true,
             (MalVal::Bool(a), MalVal::Bool(b)) => a == b,
             (MalVal::Number(a), MalVal::Number(b)) => a == b,
+            (MalVal::BigInt(a), MalVal::BigInt(b)) => a == b,
+            // NaN同士も等しいものとする
+            (MalVal::Float(a), MalVal::Float(b)) => a == b || (a.is_nan() && b.is_nan()),
+            (MalVal::Ratio(a), MalVal::Ratio(b)) => a == b,
+            (MalVal::Number(a), MalVal::BigInt(b)) | (MalVal::BigInt(b), MalVal::Number(a)) => {
+                **b == BigInt::from(*a)
+            }
             (MalVal::String(a), MalVal::String(b)) => a == b,
             (MalVal::Keyword(a), MalVal::Keyword(b)) => a == b,
             (MalVal::Symbol(a), MalVal::Symbol(b)) => a == b,
+            // 正規表現はパターンが同じなら等しい
+            (MalVal::Regex(a), MalVal::Regex(b)) => a.as_str() == b.as_str(),
+            (MalVal::Vector(a, _), MalVal::Vector(b, _)) => a == b,
+            // 遅延シーケンスは要素を計算して比較する. 計算に失敗した場合はそのエラーも比較する
+            (MalVal::LazySeq(_), _) | (_, MalVal::LazySeq(_))
+                if self.is_sequential() && other.is_sequential() =>
+            {
+                // SAFETY: どちらもシーケンス
+                self.seq().unwrap().eq(other.seq().unwrap())
+            }
             (
-                MalVal::List(a, _) | MalVal::Vector(a, _),
-                MalVal::List(b, _) | MalVal::Vector(b, _),
-            ) => a == b,
+                MalVal::List(_, _) | MalVal::Vector(_, _),
+                MalVal::List(_, _) | MalVal::Vector(_, _),
+            ) => {
+                // SAFETY: どちらもListかVector
+                let (a, b) = (self.seq_iter().unwrap(), other.seq_iter().unwrap());
+                a.len() == b.len() && a.eq(b)
+            }
             (MalVal::HashMap(a, _), MalVal::HashMap(b, _)) => a == b,
             (MalVal::HashSet(a, _), MalVal::HashSet(b, _)) => a == b,
-            (MalVal::BuiltinFn(a), MalVal::BuiltinFn(b)) => a == b,
-            _ => false, // NOTE: Func同士は常にfalse
+            // 中身が同じならHashMapとSortedMap、HashSetとSortedSetも等しい
+            (
+                MalVal::HashMap(_, _) | MalVal::SortedMap(_, _),
+                MalVal::HashMap(_, _) | MalVal::SortedMap(_, _),
+            ) => {
+                let (a, b) = (self.map_iter().unwrap(), other.map_iter().unwrap());
+                a.len() == b.len() && a.into_iter().all(|(k, v)| other.map_get(k) == Some(v))
+            }
+            (
+                MalVal::HashSet(_, _) | MalVal::SortedSet(_, _),
+                MalVal::HashSet(_, _) | MalVal::SortedSet(_, _),
+            ) => {
+                let (a, b) = (self.set_iter().unwrap(), other.set_iter().unwrap());
+                a.len() == b.len() && a.into_iter().all(|x| other.set_contains(x))
+            }
+            (MalVal::BuiltinFn(a), MalVal::BuiltinFn(b)) => std::ptr::fn_addr_eq(*a, *b),
+            (MalVal::Error(a), MalVal::Error(b)) => a == b,
+            (MalVal::Transient(a), MalVal::Transient(b)) => Rc::ptr_eq(a, b),
+            (MalVal::Reduced(a), MalVal::Reduced(b)) => a == b,
+            (MalVal::NativeFn(a), MalVal::NativeFn(b)) => Rc::ptr_eq(a, b),
+            // 関数は同じ実体の場合だけ等しい
+            (MalVal::Func(a, _), MalVal::Func(b, _)) => Rc::ptr_eq(a, b),
+            _ => false,
         }
     }
 }
 
 impl Eq for MalVal {}
 
+impl PartialOrd for MalVal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// sorted-mapなどで用いる全順序. 型が異なる場合はtype_rankの順
+/// コレクションは要素を辞書式に比較し、マップと集合はソートした要素で比較する
+impl Ord for MalVal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.type_rank()
+            .cmp(&other.type_rank())
+            .then_with(|| match (self, other) {
+                (MalVal::Bool(a), MalVal::Bool(b)) => a.cmp(b),
+                (MalVal::Number(a), MalVal::Number(b)) => a.cmp(b),
+                // 数値として等しい場合は整数 < 浮動小数点数とする. NaNは最大
+                (
+                    MalVal::Number(_) | MalVal::BigInt(_) | MalVal::Float(_) | MalVal::Ratio(_),
+                    MalVal::Number(_) | MalVal::BigInt(_) | MalVal::Float(_) | MalVal::Ratio(_),
+                ) => self
+                    .num_cmp(other)
+                    .unwrap_or_else(|| self.is_nan().cmp(&other.is_nan()))
+                    .then_with(|| {
+                        matches!(self, MalVal::Float(_)).cmp(&matches!(other, MalVal::Float(_)))
+                    }),
+                (MalVal::String(a), MalVal::String(b))
+                | (MalVal::Keyword(a), MalVal::Keyword(b))
+                | (MalVal::Symbol(a), MalVal::Symbol(b)) => a.cmp(b),
+                (MalVal::Regex(a), MalVal::Regex(b)) => a.as_str().cmp(b.as_str()),
+                (MalVal::BuiltinFn(a), MalVal::BuiltinFn(b)) => (*a as usize).cmp(&(*b as usize)),
+                // 組み込み関数 < 値を捕捉した組み込み関数
+                (MalVal::BuiltinFn(_), MalVal::NativeFn(_)) => Ordering::Less,
+                (MalVal::NativeFn(_), MalVal::BuiltinFn(_)) => Ordering::Greater,
+                (MalVal::NativeFn(a), MalVal::NativeFn(b)) => Rc::as_ptr(a).cmp(&Rc::as_ptr(b)),
+                (MalVal::Func(a, _), MalVal::Func(b, _)) => Rc::as_ptr(a).cmp(&Rc::as_ptr(b)),
+                (MalVal::Error(a), MalVal::Error(b)) => a.cmp(b),
+                (MalVal::Transient(a), MalVal::Transient(b)) => Rc::as_ptr(a).cmp(&Rc::as_ptr(b)),
+                (MalVal::Reduced(a), MalVal::Reduced(b)) => a.cmp(b),
+                _ => {
+                    if let (Some(a), Some(b)) = (self.seq_iter(), other.seq_iter()) {
+                        a.cmp(b)
+                    } else if self.is_sequential() {
+                        // 遅延シーケンスを含む場合. 計算に失敗した要素は全ての値より大きい
+                        self.seq().unwrap().cmp(other.seq().unwrap())
+                    } else if let (Some(a), Some(b)) = (self.map_iter(), other.map_iter()) {
+                        a.sorted().cmp(b.sorted())
+                    } else if let (Some(a), Some(b)) = (self.set_iter(), other.set_iter()) {
+                        a.sorted().cmp(b.sorted())
+                    } else {
+                        Ordering::Equal // nil同士
+                    }
+                }
+            })
+    }
+}
+
 impl Hash for MalVal {
     fn hash<H: Hasher>(&self, state: &mut H) {
         match self {
             MalVal::Nil => 0.hash(state),
             MalVal::Bool(b) => b.hash(state),
             MalVal::Number(n) => n.hash(state),
+            // Numberと等しくなり得る値はNumberと同じ方法でハッシュする
+            MalVal::BigInt(n) => match i64::try_from(n.as_ref()) {
+                Ok(n) => n.hash(state),
+                Err(_) => n.hash(state),
+            },
+            MalVal::Ratio(r) => {
+                state.write_u8(5);
+                r.hash(state)
+            }
+            // 0.0と-0.0、すべてのNaNは等しいので同じ値にする
+            MalVal::Float(f) => {
+                state.write_u8(4);
+                if *f == 0.0 {
+                    0.0f64.to_bits().hash(state)
+                } else if f.is_nan() {
+                    f64::NAN.to_bits().hash(state)
+                } else {
+                    f.to_bits().hash(state)
+                }
+            }
             MalVal::String(s) => {
                 state.write_u8(1);
                 s.hash(state)
@@ -144,32 +673,49 @@ impl Hash for MalVal {
                 state.write_u8(3);
                 s.hash(state)
             }
-            MalVal::List(v, _) | MalVal::Vector(v, _) => v.hash(state),
+            MalVal::Regex(re) => {
+                state.write_u8(6);
+                re.as_str().hash(state)
+            }
+            MalVal::List(_, _) | MalVal::Vector(_, _) => {
+                // ListとVectorは等しくなり得るので同じ方法でハッシュする
+                let seq = self.seq_iter().unwrap();
+                state.write_usize(seq.len());
+                seq.for_each(|e| e.hash(state));
+            }
+            // Listと等しくなり得るので同じ方法でハッシュする
+            // NOTE: 最後まで計算するので、無限の遅延シーケンスはハッシュできない
+            MalVal::LazySeq(_) => {
+                let seq = self.seq().unwrap().map_while(Result::ok).collect_vec();
+                state.write_usize(seq.len());
+                seq.iter().for_each(|e| e.hash(state));
+            }
             // ref: [集合をハッシュする (Zobrist hashing)](https://trap.jp/post/1594/)
-            MalVal::HashMap(m, _) => {
+            // HashMapとSortedMapは等しくなり得るので同じハッシュ関数を用いる
+            MalVal::HashMap(_, _) | MalVal::SortedMap(_, _) => {
+                let m = self.map_iter().unwrap();
                 state.write_usize(m.len());
                 state.write_u64(
-                    m.iter()
-                        .map(|e| m.hasher().hash_one(e))
+                    m.map(|e| FnvBuildHasher::default().hash_one(e))
                         .reduce(|a, b| a ^ b)
                         .unwrap_or(4),
                 );
             }
-            MalVal::HashSet(s, _) => {
+            MalVal::HashSet(_, _) | MalVal::SortedSet(_, _) => {
+                let s = self.set_iter().unwrap();
                 state.write_usize(s.len());
                 state.write_u64(
-                    s.iter()
-                        .map(|e| s.hasher().hash_one(e))
+                    s.map(|e| FnvBuildHasher::default().hash_one(e))
                         .reduce(|a, b| a ^ b)
                         .unwrap_or(5),
                 );
             }
             MalVal::BuiltinFn(f) => state.write_usize(f as *const _ as usize),
-            MalVal::Func(f, _) => {
-                state.write_usize(f as *const _ as usize);
-                f.rev_params.hash(state);
-                f.body.hash(state);
-            }
+            MalVal::NativeFn(f) => state.write_usize(Rc::as_ptr(f) as *const () as usize),
+            MalVal::Func(f, _) => state.write_usize(Rc::as_ptr(f) as usize),
+            MalVal::Error(e) => e.hash(state),
+            MalVal::Transient(t) => state.write_usize(Rc::as_ptr(t) as usize),
+            MalVal::Reduced(x) => x.hash(state),
         }
     }
 }
@@ -180,18 +726,19 @@ impl Display for MalVal {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Paren {
     Round,  // ()
     Square, // []
     Curly,  // {}
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Arity {
     Fixed(usize),
     Variadic(usize),
     JustOrOneLess(usize),
+    Either(usize, usize), // どちらかの個数
 }
 
 impl Display for Arity {
@@ -203,6 +750,7 @@ impl Display for Arity {
                 Arity::Fixed(n) => n.to_string(),
                 Arity::Variadic(n) => format!("{}+", n),
                 Arity::JustOrOneLess(n) => format!("{} or {}", n - 1, n),
+                Arity::Either(a, b) => format!("{} or {}", a, b),
             }
         )
     }
@@ -210,7 +758,7 @@ impl Display for Arity {
 
 pub type MalResult = Result<MalVal, MalError>;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum MalError {
     // Read時のエラー
     NoInput,
@@ -218,30 +766,238 @@ pub enum MalError {
     UncloedQuote,
     // Eval時のエラー
     DividedByZero,
+    IndexOutOfBounds(i64, usize), // 添字, 長さ
     NotFound(String),
-    InvalidType(String, String, String),
+    InvalidType(MalVal, String), // 不正な値, 期待された型
     WrongArity(String, Arity, usize),
     InvalidSyntax(String),
+    InvalidRegex(String), // 正規表現のコンパイルエラーの内容
+    PersistedTransient,   // persistent!の後にtransientを使った
+    SharedTransient,      // transientの中身が他から参照されていて書き換えられない
+    LazySeqCycle,         // 遅延シーケンスの計算中に自身を必要とした
+    Overflow(String),     // 結果が大きすぎて計算できない: 演算の名前
+    // ユーザーが投げたエラー
+    Throw(MalVal),
+    Info(String, MalVal), // ex-infoで作られたエラー: メッセージ, データ
+    // invoke-restartでrestart-caseまで巻き戻すためのエラー: リスタート名, 引数
+    Restart(String, Vec<MalVal>),
+    NoRestart(String), // 有効なrestart-caseがないリスタートを呼び出した: リスタート名
+    // 原因となった別のエラーを伴うエラー
+    Caused(Box<MalError>, Box<MalError>),
+    // トップレベルまで伝播したエラーとその時点の呼び出しスタック
+    Traced(Box<MalError>, Vec<Frame>),
+}
+
+/// 呼び出しスタックの1フレーム: 呼ばれた関数の名前と呼び出し元の式
+/// 名前は関数と共有し、呼び出しのたびに複製しない
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Frame {
+    pub name: Option<Rc<str>>,
+    pub form: MalVal,
+}
+
+impl Frame {
+    /// 名前のない関数は "function"
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("function")
+    }
+
+    /// {:name "f" :form (f x)} の形のハッシュマップに変換する
+    pub fn to_mal(&self) -> MalVal {
+        MalVal::hashmap(HashMap::from_iter([
+            (MalVal::keyword("name"), MalVal::string(self.name())),
+            (MalVal::keyword("form"), self.form.clone()),
+        ]))
+    }
+}
+
+impl Paren {
+    fn close(&self) -> &'static str {
+        match self {
+            Paren::Round => ")",
+            Paren::Square => "]",
+            Paren::Curly => "}",
+        }
+    }
+}
+
+impl MalError {
+    /// 呼び出しスタックを付与する. 既に付与されている場合はそのまま返す
+    /// NOTE: Tracedかどうかでコンディションとして通知済みかを判定するため、スタックが空でも付与する
+    pub fn with_trace(self, frames: &[Frame]) -> Self {
+        match self {
+            MalError::Traced(_, _) | MalError::Restart(_, _) => self,
+            // 内側の呼び出しが先頭に来るようにする
+            _ => MalError::Traced(Box::new(self), frames.iter().rev().cloned().collect()),
+        }
+    }
+
+    /// throwされた値をエラーに変換する: エラー値はそのまま、それ以外はThrowで包む
+    pub fn from_val(val: MalVal) -> Self {
+        match val {
+            MalVal::Error(e) => Rc::unwrap_or_clone(e),
+            _ => MalError::Throw(val),
+        }
+    }
+
+    /// 原因となったエラーを付与する
+    pub fn with_cause(self, cause: MalError) -> Self {
+        MalError::Caused(Box::new(self), Box::new(cause))
+    }
+
+    /// ex-infoのデータ、またはthrowされた値
+    pub fn data(&self) -> Option<&MalVal> {
+        match self {
+            MalError::Info(_, data) | MalError::Throw(data) => Some(data),
+            MalError::Caused(e, _) | MalError::Traced(e, _) => e.data(),
+            _ => None,
+        }
+    }
+
+    /// 呼び出しスタックを取り除いた元のエラー
+    pub fn untraced(&self) -> &MalError {
+        match self {
+            MalError::Traced(e, _) => e,
+            _ => self,
+        }
+    }
+
+    /// 呼び出しスタックをmalの値 (フレームのベクタ) として返す
+    pub fn trace(&self) -> MalVal {
+        match self {
+            MalError::Traced(_, frames) => MalVal::vec(frames.iter().map(Frame::to_mal).collect()),
+            _ => MalVal::vec(Vector::new()),
+        }
+    }
+
+    /// 機械的に判別するためのエラーの種類
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MalError::NoInput => "no-input",
+            MalError::Unbalanced(_) => "unbalanced",
+            MalError::UncloedQuote => "unclosed-quote",
+            MalError::DividedByZero => "divided-by-zero",
+            MalError::IndexOutOfBounds(_, _) => "index-out-of-bounds",
+            MalError::NotFound(_) => "not-found",
+            MalError::InvalidType(_, _) => "invalid-type",
+            MalError::WrongArity(_, _, _) => "wrong-arity",
+            MalError::InvalidSyntax(_) => "invalid-syntax",
+            MalError::InvalidRegex(_) => "invalid-regex",
+            MalError::PersistedTransient => "persisted-transient",
+            MalError::SharedTransient => "shared-transient",
+            MalError::LazySeqCycle => "lazy-seq-cycle",
+            MalError::Overflow(_) => "overflow",
+            MalError::Throw(_) => "thrown",
+            MalError::Info(_, _) => "ex-info",
+            MalError::Restart(_, _) => "restart",
+            MalError::NoRestart(_) => "control-error",
+            MalError::Caused(e, _) | MalError::Traced(e, _) => e.kind(),
+        }
+    }
+
+    /// {:type :wrong-arity :message "..." :expected "1" :got 2} の形のハッシュマップに変換する
+    pub fn to_mal(&self) -> MalVal {
+        MalVal::hashmap(
+            self.entries()
+                .into_iter()
+                .map(|(k, v)| (MalVal::keyword(k), v))
+                .collect(),
+        )
+    }
+
+    fn entries(&self) -> Vec<(&'static str, MalVal)> {
+        match self {
+            MalError::Caused(e, cause) => {
+                let mut entries = e.entries();
+                entries.push(("cause", cause.to_mal()));
+                entries
+            }
+            MalError::Traced(e, frames) => {
+                let mut entries = e.entries();
+                if !frames.is_empty() {
+                    entries.push(("trace", self.trace()));
+                }
+                entries
+            }
+            _ => {
+                let mut entries = vec![
+                    ("type", MalVal::keyword(self.kind())),
+                    ("message", MalVal::string(self.to_string())),
+                ];
+                match self {
+                    MalError::Unbalanced(p) => {
+                        entries.push(("expected", MalVal::string(p.close())))
+                    }
+                    MalError::IndexOutOfBounds(i, len) => entries.extend([
+                        ("index", MalVal::Number(*i)),
+                        ("count", MalVal::Number(*len as i64)),
+                    ]),
+                    MalError::NotFound(s) => entries.push(("symbol", MalVal::symbol(s.as_str()))),
+                    MalError::InvalidType(val, expected) => entries.extend([
+                        ("value", val.clone()),
+                        ("expected", MalVal::string(expected.as_str())),
+                        ("got", MalVal::string(val.type_str())),
+                    ]),
+                    MalError::WrongArity(name, expected, got) => entries.extend([
+                        ("name", MalVal::string(name.as_str())),
+                        ("expected", MalVal::string(expected.to_string())),
+                        ("got", MalVal::Number(*got as i64)),
+                    ]),
+                    MalError::Overflow(name) => {
+                        entries.push(("name", MalVal::string(name.as_str())))
+                    }
+                    MalError::Throw(val) => entries.push(("value", val.clone())),
+                    MalError::Info(_, data) => entries.push(("data", data.clone())),
+                    MalError::Restart(name, args) => entries.extend([
+                        ("name", MalVal::keyword(name.as_str())),
+                        ("args", MalVal::list(args.clone())),
+                    ]),
+                    MalError::NoRestart(name) => {
+                        entries.push(("name", MalVal::keyword(name.as_str())))
+                    }
+                    _ => {}
+                }
+                entries
+            }
+        }
+    }
+
+    /// エラーメッセージに原因と呼び出しスタックを付け加えたもの
+    pub fn report(&self) -> String {
+        let mut report = self.to_string();
+        let mut source = std::error::Error::source(self);
+        while let Some(cause) = source {
+            report.push_str(&format!("\ncaused by: {}", cause));
+            source = cause.source();
+        }
+        if let MalError::Traced(_, frames) = self {
+            frames
+                .iter()
+                .for_each(|fr| report.push_str(&format!("\n  at {} {}", fr.name(), fr.form)));
+        }
+        report
+    }
 }
 
 impl Display for MalError {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         match self {
             MalError::NoInput => write!(f, "no input"),
-            MalError::Unbalanced(p) => write!(
-                f,
-                "expected {}, got EOF",
-                match p {
-                    Paren::Round => ")",
-                    Paren::Square => "]",
-                    Paren::Curly => "}",
-                }
-            ),
+            MalError::Unbalanced(p) => write!(f, "expected {}, got EOF", p.close()),
             MalError::UncloedQuote => write!(f, "expected \", got EOF"),
             MalError::DividedByZero => write!(f, "divided by zero"),
+            MalError::IndexOutOfBounds(i, len) => {
+                write!(f, "index {} out of bounds for length {}", i, len)
+            }
             MalError::NotFound(s) => write!(f, "symbol \'{}\' not found", s),
-            MalError::InvalidType(name, expected, got) => {
-                write!(f, "expected {} for {}, got {}", expected, name, got)
+            MalError::InvalidType(val, expected) => {
+                write!(
+                    f,
+                    "expected {} for {}, got {}",
+                    expected,
+                    val,
+                    val.type_str()
+                )
             }
             MalError::WrongArity(name, expected, got) => write!(
                 f,
@@ -249,6 +1005,26 @@ impl Display for MalError {
                 name, expected, got
             ),
             MalError::InvalidSyntax(s) => write!(f, "invalid syntax: {}", s),
+            MalError::InvalidRegex(s) => write!(f, "invalid regex: {}", s),
+            MalError::PersistedTransient => write!(f, "transient used after persistent! call"),
+            MalError::SharedTransient => write!(f, "transient collection is shared"),
+            MalError::LazySeqCycle => write!(f, "lazy sequence depends on its own value"),
+            MalError::Overflow(name) => write!(f, "integer overflow in {}", name),
+            MalError::Throw(val) => write!(f, "{}", val),
+            MalError::Info(msg, _) => write!(f, "{}", msg),
+            MalError::Restart(name, _) => write!(f, "restart :{} invoked", name),
+            MalError::NoRestart(name) => write!(f, "no restart named :{} is active", name),
+            MalError::Caused(e, _) | MalError::Traced(e, _) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for MalError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MalError::Caused(_, cause) => Some(cause.as_ref()),
+            MalError::Traced(e, _) => e.source(),
+            _ => None,
         }
     }
 }
@@ -256,6 +1032,7 @@ impl Display for MalError {
 #[cfg(test)]
 mod tests {
     use std::hash::{Hash, Hasher};
+    use std::rc::Rc;
 
     use super::MalVal;
 
@@ -292,4 +1069,100 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_seq_hash() {
+        let list = MalVal::list(vec![MalVal::Number(1), MalVal::Number(2)]);
+        let vec = MalVal::vec(im_rc::vector![MalVal::Number(1), MalVal::Number(2)]);
+        assert_eq!(list, vec);
+
+        let map: MalVal = MalVal::hashmap([(vec, MalVal::Bool(true))].into_iter().collect());
+        let MalVal::HashMap(m, _) = map else {
+            unreachable!()
+        };
+        assert_eq!(m.get(&list), Some(&MalVal::Bool(true)));
+    }
+
+    #[test]
+    fn test_ord() {
+        use crate::sorted::Sorted;
+        use fnv::FnvBuildHasher;
+        use std::hash::BuildHasher;
+
+        let v = vec![
+            MalVal::Nil,
+            MalVal::Bool(false),
+            MalVal::Bool(true),
+            MalVal::Number(-1),
+            MalVal::Number(2),
+            MalVal::string("a"),
+            MalVal::keyword("a"),
+            MalVal::symbol("a"),
+            MalVal::list(vec![MalVal::Number(1)]),
+            MalVal::vec(im_rc::vector![MalVal::Number(1), MalVal::Number(0)]),
+        ];
+        let mut c = v.clone();
+        c.shuffle(&mut thread_rng());
+        c.sort();
+        assert_eq!(c, v);
+
+        let mut sorted = Sorted::new(None);
+        let mut hashmap = im_rc::HashMap::default();
+        for i in [3, 1, 2] {
+            sorted.insert((MalVal::Number(i), MalVal::Nil)).unwrap();
+            hashmap.insert(MalVal::Number(i), MalVal::Nil);
+        }
+        let (sorted, hashmap) = (MalVal::sorted_map(sorted), MalVal::hashmap(hashmap));
+        assert_eq!(sorted, hashmap);
+        assert_eq!(sorted.cmp(&hashmap), std::cmp::Ordering::Equal);
+        let hasher = FnvBuildHasher::default();
+        assert_eq!(hasher.hash_one(&sorted), hasher.hash_one(&hashmap));
+    }
+
+    #[test]
+    fn test_bigint_eq() {
+        use super::BigInt;
+        use fnv::FnvBuildHasher;
+        use std::hash::BuildHasher;
+
+        // 正規化されていないBigIntもNumberと等しく、同じハッシュ値になる
+        let big = MalVal::BigInt(Rc::new(BigInt::from(42)));
+        assert_eq!(big, MalVal::Number(42));
+        let hasher = FnvBuildHasher::default();
+        assert_eq!(hasher.hash_one(&big), hasher.hash_one(MalVal::Number(42)));
+        assert_eq!(MalVal::from(BigInt::from(42)), MalVal::Number(42));
+        assert!(MalVal::from(BigInt::from(i64::MAX) + 1) > MalVal::Number(i64::MAX));
+    }
+
+    #[test]
+    fn test_error_to_mal() {
+        use super::{Arity, MalError};
+        use std::error::Error;
+
+        let err = MalError::WrongArity("f".to_string(), Arity::Fixed(1), 2);
+        assert_eq!(
+            err.to_mal(),
+            MalVal::hashmap(
+                [
+                    ("type", MalVal::keyword("wrong-arity")),
+                    ("message", MalVal::string(err.to_string())),
+                    ("name", MalVal::string("f")),
+                    ("expected", MalVal::string("1")),
+                    ("got", MalVal::Number(2)),
+                ]
+                .into_iter()
+                .map(|(k, v)| (MalVal::keyword(k), v))
+                .collect()
+            )
+        );
+
+        let err = MalError::InvalidType(MalVal::keyword("a"), "number".to_string())
+            .with_cause(MalError::DividedByZero);
+        assert_eq!(err.kind(), "invalid-type");
+        assert_eq!(err.source().unwrap().to_string(), "divided by zero");
+        assert_eq!(
+            err.report(),
+            "expected number for :a, got keyword\ncaused by: divided by zero"
+        );
+    }
 }